@@ -0,0 +1,58 @@
+//! Buffers taken and returned from many threads at once, through the shared freelists alone
+//! (`thread_cache: 0`) and through the per-thread caches in front of them.
+//!
+//! Run with `cargo bench --bench thread_cache`.
+
+use std::hint::black_box;
+use std::sync::Barrier;
+use std::time::{Duration, Instant};
+
+use async_io_bufpool::{BufPool, BufPoolConfig};
+use futures_util::FutureExt;
+
+const ITERS: usize = 200_000;
+
+/// Time `threads` threads each leasing and returning a buffer `ITERS` times.
+fn run(config: &BufPoolConfig, threads: usize) -> Duration {
+    let pool = BufPool::from_config(config.clone());
+    pool.prewarm(threads * 2);
+    let barrier = Barrier::new(threads + 1);
+    std::thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                barrier.wait();
+                for _ in 0..ITERS {
+                    let lease = pool.acquire().now_or_never().unwrap();
+                    black_box(&lease);
+                }
+                barrier.wait();
+            });
+        }
+        barrier.wait();
+        let start = Instant::now();
+        barrier.wait();
+        start.elapsed()
+    })
+}
+
+fn main() {
+    let shared = BufPoolConfig {
+        thread_cache: 0,
+        ..Default::default()
+    };
+    let cached = BufPoolConfig::default();
+    println!(
+        "threads  shared ns/op  thread_cache={} ns/op",
+        cached.thread_cache
+    );
+    for threads in [1, 2, 4, 8, 16] {
+        let per_op = |elapsed: Duration| elapsed.as_nanos() as f64 / (threads * ITERS) as f64;
+        let shared = (0..3).map(|_| run(&shared, threads)).min().unwrap();
+        let cached = (0..3).map(|_| run(&cached, threads)).min().unwrap();
+        println!(
+            "{threads:>7}  {:>12.1}  {:>19.1}",
+            per_op(shared),
+            per_op(cached)
+        );
+    }
+}
@@ -0,0 +1,746 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{IoSlice, IoSliceMut};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_timer::Delay;
+use futures_util::{future::poll_fn, AsyncBufRead, AsyncRead, AsyncWrite};
+
+use crate::{
+    pool::PoolRef, retry_interrupted, take_buf_for, time::Instant, BufGuard, BufPool, BUF_SIZE,
+};
+
+/// A reader that ends after `limit` bytes, like `AsyncReadExt::take`, for enforcing a body
+/// length while still using the `pooled_*` functions on it.
+///
+/// Reads are cut down to what's left of the limit, so a pooled read or copy never pulls bytes
+/// past it out of the inner reader, and at the limit it reports a clean EOF. Whether the inner
+/// reader ended early shows in [`PooledTake::remaining`] staying above zero.
+pub struct PooledTake<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: AsyncRead + Unpin> PooledTake<R> {
+    /// Read at most `limit` bytes from `inner`.
+    pub fn new(inner: R, limit: u64) -> Self {
+        Self {
+            inner,
+            remaining: limit,
+        }
+    }
+
+    /// How many more bytes may be read.
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Allow `limit` more bytes from here on, e.g. for the next message on the same connection.
+    pub fn set_limit(&mut self, limit: u64) {
+        self.remaining = limit;
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// The largest read that stays within the limit.
+    fn allowed(&self, len: usize) -> usize {
+        usize::try_from(self.remaining).map_or(len, |remaining| remaining.min(len))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for PooledTake<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let max = self.allowed(buf.len());
+        if max == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        let n = std::task::ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf[..max]))?;
+        self.remaining -= n as u64;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncBufRead for PooledTake<R> {
+    fn poll_fill_buf(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<&[u8], std::io::Error>> {
+        let this = self.get_mut();
+        if this.remaining == 0 {
+            return Poll::Ready(Ok(&[]));
+        }
+        let avail = std::task::ready!(Pin::new(&mut this.inner).poll_fill_buf(cx))?;
+        let max = usize::try_from(this.remaining).map_or(avail.len(), |rem| rem.min(avail.len()));
+        Poll::Ready(Ok(&avail[..max]))
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        let amt = self.allowed(amt);
+        self.remaining -= amt as u64;
+        Pin::new(&mut self.inner).consume(amt);
+    }
+}
+
+/// A reader that mirrors everything read through it into a writer, e.g. to archive raw traffic
+/// while the main pipeline parses it.
+///
+/// Each chunk read is copied into a pooled staging buffer and written out from there, so the
+/// mirror costs one extra copy per chunk rather than a second pass over the data. The staged
+/// chunk has to reach the writer before the next read goes through, so a slow writer slows the
+/// reader down instead of queueing up memory. Errors from the writer come out of the read that
+/// hit them. At EOF the writer is flushed before the EOF is passed on; to stop early without
+/// losing bytes already read, call [`PooledTeeReader::flush`] first.
+pub struct PooledTeeReader<R, W> {
+    reader: R,
+    writer: W,
+    staged: Option<BufGuard>,
+    pos: usize,
+    /// A write error that came up after the read it belongs to had already returned its bytes.
+    failed: Option<std::io::Error>,
+    /// The biggest chunk a staging buffer can hold.
+    max_chunk: usize,
+}
+
+impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> PooledTeeReader<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            staged: None,
+            pos: 0,
+            failed: None,
+            max_chunk: BufPool::global().classes().last().unwrap_or(BUF_SIZE),
+        }
+    }
+
+    pub fn reader(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+
+    /// Unwrap the reader and writer. Bytes read but not mirrored yet are lost, see
+    /// [`PooledTeeReader::flush`].
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+
+    /// Write out whatever has been read but not mirrored yet, and flush the writer.
+    pub async fn flush(&mut self) -> Result<(), std::io::Error> {
+        poll_fn(|cx| {
+            if let Some(err) = self.failed.take() {
+                return Poll::Ready(Err(err));
+            }
+            std::task::ready!(self.poll_drain(cx))?;
+            Pin::new(&mut self.writer).poll_flush(cx)
+        })
+        .await
+    }
+
+    /// Write the staged chunk to the writer, giving its buffer back once it's all out.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        while let Some(staged) = &self.staged {
+            let n = std::task::ready!(retry_interrupted(|| {
+                Pin::new(&mut self.writer).poll_write(cx, &staged[self.pos..])
+            }))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.pos += n;
+            if self.pos == staged.len() {
+                self.staged = None;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> AsyncRead for PooledTeeReader<R, W> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = &mut *self;
+        if let Some(err) = this.failed.take() {
+            return Poll::Ready(Err(err));
+        }
+        std::task::ready!(this.poll_drain(cx))?;
+        let max = buf.len().min(this.max_chunk);
+        let n = std::task::ready!(Pin::new(&mut this.reader).poll_read(cx, &mut buf[..max]))?;
+        if n == 0 {
+            if max != 0 {
+                std::task::ready!(Pin::new(&mut this.writer).poll_flush(cx))?;
+            }
+            return Poll::Ready(Ok(0));
+        }
+        // the caller already has the bytes, so the staging buffer can't wait on the pool's budget
+        let mut staged = BufGuard::new(take_buf_for(n), PoolRef::Global, 0);
+        staged.clear();
+        staged.extend_from_slice(&buf[..n]);
+        this.staged = Some(staged);
+        this.pos = 0;
+        // start the write now; whatever doesn't go through gets finished before the next read
+        if let Poll::Ready(Err(err)) = this.poll_drain(cx) {
+            // the read already succeeded, so hold the error for the next one
+            this.staged = None;
+            this.failed = Some(err);
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+/// Concatenate readers into one, e.g. a header prelude followed by a body stream, that reads each
+/// to EOF in turn.
+///
+/// A reader finishing moves on to the next within the same read, so [`crate::pooled_copy`] and
+/// the `pooled_read_*` functions only see EOF once all of them are done. Readers of different
+/// types go in as `Box<dyn AsyncRead + Unpin + Send>`.
+pub fn pooled_chain<R, I>(readers: I) -> PooledChain<R>
+where
+    R: AsyncRead + Unpin,
+    I: IntoIterator<Item = R>,
+{
+    PooledChain {
+        readers: readers.into_iter().collect(),
+    }
+}
+
+/// The reader returned by [`pooled_chain`].
+pub struct PooledChain<R> {
+    readers: VecDeque<R>,
+}
+
+impl<R: AsyncRead + Unpin> PooledChain<R> {
+    /// Queue another reader after the ones already there.
+    pub fn push(&mut self, reader: R) {
+        self.readers.push_back(reader);
+    }
+
+    /// The readers not finished yet, starting with the current one.
+    pub fn readers(&self) -> impl Iterator<Item = &R> {
+        self.readers.iter()
+    }
+
+    pub fn into_inner(self) -> VecDeque<R> {
+        self.readers
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for PooledChain<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        while let Some(reader) = self.readers.front_mut() {
+            match std::task::ready!(Pin::new(reader).poll_read(cx, buf))? {
+                0 => drop(self.readers.pop_front()),
+                n => return Poll::Ready(Ok(n)),
+            }
+        }
+        Poll::Ready(Ok(0))
+    }
+}
+
+/// Byte and call counters shared by any number of [`Metered`] streams, e.g. both halves of one
+/// connection. Clones count into the same totals.
+#[derive(Clone)]
+pub struct Meter {
+    counters: Arc<Counters>,
+}
+
+struct Counters {
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    reads: AtomicU64,
+    writes: AtomicU64,
+    /// Nanoseconds from `created` to the first byte moved, or `u64::MAX` until then.
+    first_byte: AtomicU64,
+    created: Instant,
+}
+
+/// A snapshot of a [`Meter`]'s counters, from [`Meter::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MeterStats {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Reads that returned data.
+    pub reads: u64,
+    /// Writes that took data.
+    pub writes: u64,
+    /// Time from the meter's creation to the first byte read or written, if any has been yet.
+    pub time_to_first_byte: Option<Duration>,
+}
+
+impl Meter {
+    /// Fresh counters, with the time to first byte measured from now.
+    pub fn new() -> Self {
+        Self {
+            counters: Arc::new(Counters {
+                bytes_read: AtomicU64::new(0),
+                bytes_written: AtomicU64::new(0),
+                reads: AtomicU64::new(0),
+                writes: AtomicU64::new(0),
+                first_byte: AtomicU64::new(u64::MAX),
+                created: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn stats(&self) -> MeterStats {
+        let counters = &self.counters;
+        let first_byte = counters.first_byte.load(Ordering::Relaxed);
+        MeterStats {
+            bytes_read: counters.bytes_read.load(Ordering::Relaxed),
+            bytes_written: counters.bytes_written.load(Ordering::Relaxed),
+            reads: counters.reads.load(Ordering::Relaxed),
+            writes: counters.writes.load(Ordering::Relaxed),
+            time_to_first_byte: (first_byte != u64::MAX).then(|| Duration::from_nanos(first_byte)),
+        }
+    }
+
+    fn record(&self, bytes: &AtomicU64, calls: &AtomicU64, n: usize) {
+        if n == 0 {
+            return;
+        }
+        bytes.fetch_add(n as u64, Ordering::Relaxed);
+        calls.fetch_add(1, Ordering::Relaxed);
+        let counters = &self.counters;
+        if counters.first_byte.load(Ordering::Relaxed) == u64::MAX {
+            let nanos = counters
+                .created
+                .elapsed()
+                .as_nanos()
+                .min(u64::MAX as u128 - 1) as u64;
+            // only the first stream to get here sets it
+            let _ = counters.first_byte.compare_exchange(
+                u64::MAX,
+                nanos,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+        }
+    }
+
+    fn record_read(&self, n: usize) {
+        self.record(&self.counters.bytes_read, &self.counters.reads, n);
+    }
+
+    fn record_write(&self, n: usize) {
+        self.record(&self.counters.bytes_written, &self.counters.writes, n);
+    }
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A reader or writer that counts what goes through it into a [`Meter`], for per-connection
+/// accounting around the `pooled_*` functions.
+pub struct Metered<T> {
+    inner: T,
+    meter: Meter,
+}
+
+impl<T> Metered<T> {
+    /// Count into a fresh meter of its own.
+    pub fn new(inner: T) -> Self {
+        Self::with_meter(inner, Meter::new())
+    }
+
+    /// Count into `meter`, possibly shared with other streams.
+    pub fn with_meter(inner: T, meter: Meter) -> Self {
+        Self { inner, meter }
+    }
+
+    pub fn meter(&self) -> &Meter {
+        &self.meter
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Metered<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let n = std::task::ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.meter.record_read(n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_read_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let n = std::task::ready!(Pin::new(&mut self.inner).poll_read_vectored(cx, bufs))?;
+        self.meter.record_read(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Metered<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let n = std::task::ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.meter.record_write(n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let n = std::task::ready!(Pin::new(&mut self.inner).poll_write_vectored(cx, bufs))?;
+        self.meter.record_write(n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// A reader that hands every chunk read through it to `f`, e.g. for sniffing the protocol or
+/// sampling traffic into a log, while passing the data on unchanged.
+///
+/// `f` sees the caller's own buffer right after the read fills it, which under the `pooled_*`
+/// functions is the pooled buffer, so watching costs no copy. Empty reads aren't passed on.
+pub struct InspectReader<R, F> {
+    inner: R,
+    f: F,
+}
+
+impl<R: AsyncRead + Unpin, F: FnMut(&[u8])> InspectReader<R, F> {
+    pub fn new(inner: R, f: F) -> Self {
+        Self { inner, f }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin, F: FnMut(&[u8]) + Unpin> AsyncRead for InspectReader<R, F> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = &mut *self;
+        let n = std::task::ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if n != 0 {
+            (this.f)(&buf[..n]);
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+/// A reader that feeds everything read through it to a hasher, so a [`crate::pooled_copy`] can be
+/// verified in the same pass, as with [`crate::pooled_copy_digest`] but for any consumer.
+///
+/// Only what was actually read through it is hashed, so finish reading to EOF before
+/// [`HashingReader::finalize`] if the digest is meant to cover the whole stream.
+#[cfg(feature = "digest")]
+pub struct HashingReader<R, D> {
+    inner: R,
+    hasher: D,
+}
+
+#[cfg(feature = "digest")]
+impl<R: AsyncRead + Unpin, D: digest::Digest> HashingReader<R, D> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: D::new(),
+        }
+    }
+
+    /// The hasher, which has seen everything read so far.
+    pub fn hasher(&self) -> &D {
+        &self.hasher
+    }
+
+    /// The digest of everything read so far.
+    pub fn finalize(self) -> digest::Output<D> {
+        self.hasher.finalize()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwrap the reader and the hasher.
+    pub fn into_inner(self) -> (R, D) {
+        (self.inner, self.hasher)
+    }
+}
+
+#[cfg(feature = "digest")]
+impl<R: AsyncRead + Unpin, D: digest::Digest + Unpin> AsyncRead for HashingReader<R, D> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = &mut *self;
+        let n = std::task::ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.hasher.update(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+}
+
+/// The per-operation deadline of [`TimeoutReader`] and [`TimeoutWriter`].
+struct OpTimer {
+    timeout: Duration,
+    /// Armed when an operation first returns `Pending`, cleared when it completes.
+    timer: Option<Delay>,
+}
+
+impl OpTimer {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            timer: None,
+        }
+    }
+
+    /// Pass on the outcome of one poll of the operation, turning `Pending` into `TimedOut` once
+    /// it has been pending for the whole timeout.
+    fn poll<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        res: Poll<Result<T, std::io::Error>>,
+    ) -> Poll<Result<T, std::io::Error>> {
+        if res.is_ready() {
+            self.timer = None;
+            return res;
+        }
+        let timeout = self.timeout;
+        let timer = self.timer.get_or_insert_with(|| Delay::new(timeout));
+        std::task::ready!(Pin::new(timer).poll(cx));
+        self.timer = None;
+        trace_event!(debug, timeout = ?self.timeout, "I/O operation timed out");
+        Poll::Ready(Err(std::io::ErrorKind::TimedOut.into()))
+    }
+}
+
+/// A reader whose reads fail with `TimedOut` if the inner reader stays not ready for longer than
+/// the timeout, e.g. to drop a stalled peer in the middle of a pooled copy.
+///
+/// Each read gets the full timeout, counted from its first `Pending`. The timer is a
+/// `futures-timer` one, so any executor will do. For a deadline on the whole transfer instead,
+/// see [`crate::PooledCopy::deadline`] or [`crate::pooled_copy_with_deadline`].
+pub struct TimeoutReader<R> {
+    inner: R,
+    timer: OpTimer,
+}
+
+impl<R: AsyncRead + Unpin> TimeoutReader<R> {
+    pub fn new(inner: R, timeout: Duration) -> Self {
+        Self {
+            inner,
+            timer: OpTimer::new(timeout),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timer.timeout
+    }
+
+    /// Change the timeout, from the next read on.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timer = OpTimer::new(timeout);
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for TimeoutReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+        this.timer.poll(cx, res)
+    }
+
+    fn poll_read_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_read_vectored(cx, bufs);
+        this.timer.poll(cx, res)
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncBufRead for TimeoutReader<R> {
+    fn poll_fill_buf(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<&[u8], std::io::Error>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.inner).poll_fill_buf(cx);
+        this.timer.poll(cx, res)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.inner).consume(amt);
+    }
+}
+
+/// A writer whose writes, flushes and closes fail with `TimedOut` if the inner writer stays not
+/// ready for longer than the timeout, as [`TimeoutReader`] does for reads.
+pub struct TimeoutWriter<W> {
+    inner: W,
+    timer: OpTimer,
+}
+
+impl<W: AsyncWrite + Unpin> TimeoutWriter<W> {
+    pub fn new(inner: W, timeout: Duration) -> Self {
+        Self {
+            inner,
+            timer: OpTimer::new(timeout),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timer.timeout
+    }
+
+    /// Change the timeout, from the next operation on.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timer = OpTimer::new(timeout);
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for TimeoutWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+        this.timer.poll(cx, res)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        this.timer.poll(cx, res)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_flush(cx);
+        this.timer.poll(cx, res)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_close(cx);
+        this.timer.poll(cx, res)
+    }
+}
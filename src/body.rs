@@ -0,0 +1,85 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures_util::AsyncRead;
+use http_body::{Body, Frame};
+
+use crate::{chunk_size, init_buf, pool::PoolRef, retry_interrupted, BufPool, PooledBytes};
+
+/// An [`http_body::Body`] streaming a reader's contents as pooled frames, e.g. for serving files
+/// with hyper or axum.
+///
+/// Each frame is one read into a buffer of the frame size, the pool's buffer size unless set with
+/// [`PooledBody::frame_size`]. Frames are the pooled buffers themselves, which go back to the pool
+/// once hyper is done writing them out, so a slow client holds at most the frames in flight.
+pub struct PooledBody<R> {
+    reader: R,
+    pool: PoolRef,
+    frame_size: usize,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> PooledBody<R> {
+    /// Stream `reader` until EOF.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pool: PoolRef::Global,
+            frame_size: chunk_size(),
+            done: false,
+        }
+    }
+
+    /// Read frames of up to `size` bytes (at least 1), capped at the pool's largest size class.
+    pub fn frame_size(mut self, size: usize) -> Self {
+        self.frame_size = size.max(1);
+        self
+    }
+
+    /// Take frame buffers from `pool` instead of the global pool.
+    pub fn pool(mut self, pool: &BufPool) -> Self {
+        self.pool = PoolRef::Owned(pool.clone());
+        self
+    }
+
+    /// Get back the reader, positioned after the last frame read.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: AsyncRead + Unpin> Body for PooledBody<R> {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, std::io::Error>>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let this = &mut *self;
+        let (mut free_buf, _) = std::task::ready!(this.pool.poll_acquire(cx, this.frame_size));
+        let buf = init_buf(&mut free_buf, this.frame_size);
+        let res = std::task::ready!(retry_interrupted(|| {
+            Pin::new(&mut this.reader).poll_read(cx, buf)
+        }));
+        match res {
+            Ok(0) => {
+                this.done = true;
+                Poll::Ready(None)
+            }
+            Ok(n) => Poll::Ready(Some(Ok(Frame::data(PooledBytes::new(free_buf, n).into())))),
+            Err(err) => {
+                this.done = true;
+                Poll::Ready(Some(Err(err)))
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done
+    }
+}
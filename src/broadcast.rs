@@ -0,0 +1,421 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::{future::poll_fn, AsyncRead, AsyncWrite};
+
+use crate::{
+    chunk_size, init_buf, pool::PoolRef, retry_interrupted, BufGuard, BufPool, CopyError, BUF_SIZE,
+};
+
+/// What [`pooled_broadcast`] does about writers that fail or fall behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkPolicy {
+    /// Fail the whole broadcast as soon as any writer fails. Every chunk is written to all writers
+    /// before the next one is read.
+    Error,
+    /// Stop writing to a writer once it fails and keep serving the others, still in lockstep.
+    Drop,
+    /// Let writers fall up to this many bytes behind the fastest one before reading pauses, so a
+    /// briefly slow writer doesn't stall the rest. Reads are cut short as needed to keep every
+    /// writer within that bound; zero means lockstep, as with [`SinkPolicy::Error`]. Failures abort
+    /// as with [`SinkPolicy::Error`].
+    Buffer(usize),
+}
+
+/// Read `reader` to EOF once and write every chunk to each of `writers`, all of them concurrently.
+///
+/// Chunks are kept in pooled buffers until the slowest writer is done with them. Resolves to one
+/// entry per writer: the bytes it received, or the error it was dropped with under
+/// [`SinkPolicy::Drop`]. A read error fails the whole broadcast, and so does every writer having
+/// been dropped, rather than reading on with nowhere to send the data.
+pub async fn pooled_broadcast<W: AsyncWrite + Unpin>(
+    mut reader: impl AsyncRead + Unpin,
+    writers: &mut [W],
+    policy: SinkPolicy,
+) -> Result<Vec<Result<u64, CopyError>>, CopyError> {
+    let mut window = Window {
+        chunks: VecDeque::new(),
+        base: 0,
+        end: 0,
+    };
+    let mut sinks: Vec<Sink> = writers
+        .iter()
+        .map(|_| Sink {
+            pos: 0,
+            need_flush: false,
+            failed: None,
+        })
+        .collect();
+    let mut read_done = false;
+    poll_fn(|cx| loop {
+        let mut progress = false;
+        let mut read_pending = false;
+
+        let lag = window.end - window.min_pos(&sinks);
+        // how much may be read now without leaving the slowest writer further behind than allowed
+        let room = match policy {
+            SinkPolicy::Buffer(cap) if cap > 0 => (cap as u64).saturating_sub(lag),
+            _ if lag == 0 => u64::MAX,
+            _ => 0,
+        };
+        if !read_done && room > 0 {
+            match PoolRef::Global.poll_acquire(cx, chunk_size()) {
+                Poll::Ready((mut free_buf, _)) => {
+                    let want = usize::try_from(room).map_or(chunk_size(), |r| r.min(chunk_size()));
+                    let read = retry_interrupted(|| {
+                        Pin::new(&mut reader).poll_read(cx, init_buf(&mut free_buf, want))
+                    });
+                    match read {
+                        Poll::Ready(Ok(0)) => {
+                            read_done = true;
+                            progress = true;
+                        }
+                        Poll::Ready(Ok(n)) => {
+                            window.chunks.push_back((free_buf, n));
+                            window.end += n as u64;
+                            progress = true;
+                        }
+                        Poll::Ready(Err(err)) => return Poll::Ready(Err(CopyError::Read(err))),
+                        Poll::Pending => read_pending = true,
+                    }
+                }
+                // the pool's budget is used up; writers draining the window give some back
+                Poll::Pending => read_pending = true,
+            }
+        }
+
+        for (sink, writer) in sinks.iter_mut().zip(writers.iter_mut()) {
+            if sink.failed.is_some() {
+                continue;
+            }
+            let res = if sink.pos < window.end {
+                let chunk = window.slice_from(sink.pos);
+                match Pin::new(&mut *writer).poll_write(cx, chunk) {
+                    Poll::Ready(Ok(0)) => {
+                        Err(CopyError::Write(std::io::ErrorKind::WriteZero.into()))
+                    }
+                    Poll::Ready(Ok(n)) => {
+                        sink.pos += n as u64;
+                        sink.need_flush = true;
+                        progress = true;
+                        Ok(())
+                    }
+                    Poll::Ready(Err(err)) => Err(CopyError::Write(err)),
+                    Poll::Pending => Ok(()),
+                }
+            } else if sink.need_flush && (read_done || read_pending) {
+                // caught up and waiting on the reader: don't leave data sitting in its buffers
+                match Pin::new(&mut *writer).poll_flush(cx) {
+                    Poll::Ready(Ok(())) => {
+                        sink.need_flush = false;
+                        progress = true;
+                        Ok(())
+                    }
+                    Poll::Ready(Err(err)) => Err(CopyError::Flush(err)),
+                    Poll::Pending => Ok(()),
+                }
+            } else {
+                Ok(())
+            };
+            if let Err(err) = res {
+                if policy != SinkPolicy::Drop {
+                    return Poll::Ready(Err(err));
+                }
+                sink.failed = Some(err);
+                progress = true;
+            }
+        }
+        if !sinks.is_empty() && sinks.iter().all(|s| s.failed.is_some()) {
+            return Poll::Ready(Err(CopyError::Write(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "every writer has failed",
+            ))));
+        }
+        window.release(&sinks);
+
+        if read_done
+            && sinks
+                .iter()
+                .all(|s| s.failed.is_some() || (s.pos == window.end && !s.need_flush))
+        {
+            return Poll::Ready(Ok(sinks
+                .iter_mut()
+                .map(|s| match s.failed.take() {
+                    Some(err) => Err(err),
+                    None => Ok(s.pos),
+                })
+                .collect()));
+        }
+        if !progress {
+            return Poll::Pending;
+        }
+    })
+    .await
+}
+
+struct Sink {
+    /// Bytes written to this writer so far.
+    pos: u64,
+    need_flush: bool,
+    failed: Option<CopyError>,
+}
+
+/// Chunks that some writer still has to write, returned to the pool once all of them have.
+struct Window {
+    chunks: VecDeque<(BufGuard, usize)>,
+    /// Stream offset of the first chunk.
+    base: u64,
+    /// Stream offset just past the last chunk.
+    end: u64,
+}
+
+impl Window {
+    /// The position of the slowest writer that is still being served.
+    fn min_pos(&self, sinks: &[Sink]) -> u64 {
+        sinks
+            .iter()
+            .filter(|s| s.failed.is_none())
+            .map(|s| s.pos)
+            .min()
+            .unwrap_or(self.end)
+    }
+
+    /// The rest of the chunk containing stream offset `pos`.
+    fn slice_from(&self, pos: u64) -> &[u8] {
+        let mut offset = (pos - self.base) as usize;
+        for (buf, len) in &self.chunks {
+            if offset < *len {
+                return &buf[offset..*len];
+            }
+            offset -= len;
+        }
+        &[]
+    }
+
+    fn release(&mut self, sinks: &[Sink]) {
+        let min = self.min_pos(sinks);
+        while let Some((_, len)) = self.chunks.front() {
+            if self.base + *len as u64 > min {
+                break;
+            }
+            self.base += *len as u64;
+            self.chunks.pop_front();
+        }
+    }
+}
+
+/// A writer that fans each write out to several writers, e.g. a live client and an archive file.
+///
+/// A write is copied into a pooled buffer and the writers are fed from there, all at once by
+/// default or one after another with [`MultiWriter::sequential`]; the next write waits until every
+/// writer still being served has the previous one. [`SinkPolicy::Error`] fails the write (and
+/// flush or close) that hits a failing writer, and [`SinkPolicy::Drop`] quietly stops serving
+/// that writer until none are left. Only one write is staged at a time, so
+/// [`SinkPolicy::Buffer`] acts like [`SinkPolicy::Error`] here. The staged copy counts against
+/// the global pool's [`crate::BufPoolConfig::max_outstanding_bytes`], so a write waits while that
+/// budget is used up.
+pub struct MultiWriter<W> {
+    writers: Vec<W>,
+    members: Vec<Member>,
+    policy: SinkPolicy,
+    sequential: bool,
+    staged: Option<BufGuard>,
+    /// The biggest write a staging buffer can hold.
+    max_chunk: usize,
+}
+
+struct Member {
+    /// How much of the staged write this writer has taken.
+    pos: usize,
+    failed: Option<std::io::Error>,
+    flushed: bool,
+    closed: bool,
+}
+
+impl<W: AsyncWrite + Unpin> MultiWriter<W> {
+    pub fn new(writers: Vec<W>, policy: SinkPolicy) -> Self {
+        let members = writers
+            .iter()
+            .map(|_| Member {
+                pos: 0,
+                failed: None,
+                flushed: true,
+                closed: false,
+            })
+            .collect();
+        Self {
+            writers,
+            members,
+            policy,
+            sequential: false,
+            staged: None,
+            max_chunk: BufPool::global().classes().last().unwrap_or(BUF_SIZE),
+        }
+    }
+
+    /// Feed the writers one at a time, in order, rather than all at once.
+    pub fn sequential(mut self) -> Self {
+        self.sequential = true;
+        self
+    }
+
+    pub fn writers(&self) -> &[W] {
+        &self.writers
+    }
+
+    /// The writers dropped under [`SinkPolicy::Drop`], by index, with the error each failed with.
+    pub fn failures(&self) -> impl Iterator<Item = (usize, &std::io::Error)> {
+        self.members
+            .iter()
+            .enumerate()
+            .filter_map(|(i, m)| Some((i, m.failed.as_ref()?)))
+    }
+
+    /// Unwrap the writers. A write still staged is lost unless flushed first.
+    pub fn into_inner(self) -> Vec<W> {
+        self.writers
+    }
+
+    /// Record that a writer failed, or fail the whole operation, depending on the policy.
+    fn fail(&mut self, i: usize, err: std::io::Error) -> Result<(), std::io::Error> {
+        if self.policy != SinkPolicy::Drop {
+            self.staged = None;
+            return Err(err);
+        }
+        self.members[i].failed = Some(err);
+        if self.members.iter().all(|m| m.failed.is_some()) {
+            self.staged = None;
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "every writer has failed",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Poll `op` on each writer still being served until it reports that writer done, all of
+    /// them at once or one by one. `op` gets the staged write, or nothing.
+    fn poll_each(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut op: impl FnMut(
+            &mut W,
+            &mut Member,
+            &[u8],
+            &mut Context<'_>,
+        ) -> Poll<Result<bool, std::io::Error>>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        let mut pending = false;
+        for i in 0..self.writers.len() {
+            let staged = self.staged.as_deref().map_or(&[][..], |buf| &buf[..]);
+            let member = &mut self.members[i];
+            if member.failed.is_some() {
+                continue;
+            }
+            let res = loop {
+                match op(&mut self.writers[i], member, staged, cx) {
+                    Poll::Ready(Ok(false)) => {}
+                    Poll::Ready(Ok(true)) => break Ok(()),
+                    Poll::Ready(Err(err)) => break Err(err),
+                    Poll::Pending => {
+                        pending = true;
+                        break Ok(());
+                    }
+                }
+            };
+            if let Err(err) = res {
+                self.fail(i, err)?;
+            }
+            if pending && self.sequential {
+                break;
+            }
+        }
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Get the staged write to every writer, giving its buffer back once they all have it.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        if self.staged.is_none() {
+            return Poll::Ready(Ok(()));
+        }
+        std::task::ready!(self.poll_each(cx, |writer, m, staged, cx| {
+            if m.pos == staged.len() {
+                return Poll::Ready(Ok(true));
+            }
+            let n = std::task::ready!(retry_interrupted(|| {
+                Pin::new(&mut *writer).poll_write(cx, &staged[m.pos..])
+            }))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            m.pos += n;
+            m.flushed = false;
+            Poll::Ready(Ok(false))
+        }))?;
+        self.staged = None;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for MultiWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = &mut *self;
+        std::task::ready!(this.poll_drain(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let n = buf.len().min(this.max_chunk);
+        // held until every writer has it, so it waits on the budget like a copy's chunks
+        let (mut staged, _) = std::task::ready!(PoolRef::Global.poll_acquire(cx, n));
+        staged.clear();
+        staged.extend_from_slice(&buf[..n]);
+        this.staged = Some(staged);
+        for member in &mut this.members {
+            member.pos = 0;
+        }
+        // start the writes now; whatever doesn't go through gets finished before the next write
+        if let Poll::Ready(Err(err)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        let this = &mut *self;
+        std::task::ready!(this.poll_drain(cx))?;
+        this.poll_each(cx, |writer, m, _, cx| {
+            if !m.flushed {
+                std::task::ready!(Pin::new(writer).poll_flush(cx))?;
+                m.flushed = true;
+            }
+            Poll::Ready(Ok(true))
+        })
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        let this = &mut *self;
+        std::task::ready!(this.poll_drain(cx))?;
+        this.poll_each(cx, |writer, m, _, cx| {
+            if !m.closed {
+                std::task::ready!(Pin::new(writer).poll_close(cx))?;
+                m.closed = true;
+            }
+            Poll::Ready(Ok(true))
+        })
+    }
+}
@@ -0,0 +1,302 @@
+use std::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use crate::BufferSource;
+
+/// The storage behind one pooled buffer: `capacity` bytes aligned to `align`, of which the first
+/// `len` have been initialized.
+///
+/// Pools hold these rather than `Vec<u8>`s because a `Vec<u8>` allocation can't promise more than
+/// byte alignment, which direct I/O needs.
+pub(crate) struct PoolBuf {
+    ptr: NonNull<u8>,
+    capacity: usize,
+    len: usize,
+    align: usize,
+    /// Where the memory goes back to, if not the global allocator.
+    source: Option<Arc<dyn BufferSource>>,
+    /// A checksum of the contents taken when the buffer went back to its pool, and checked when
+    /// it comes out again.
+    #[cfg(feature = "debug-pool")]
+    returned: Option<u64>,
+}
+
+/// Bytes of canary right after each buffer, and at least right before it.
+#[cfg(feature = "debug-pool")]
+const CANARY_LEN: usize = 16;
+
+#[cfg(feature = "debug-pool")]
+const CANARY: u8 = 0xcb;
+
+/// How far into its allocation a buffer aligned to `align` starts.
+#[cfg(feature = "debug-pool")]
+fn front_pad(align: usize) -> usize {
+    align.max(CANARY_LEN)
+}
+
+#[cfg(not(feature = "debug-pool"))]
+fn front_pad(_align: usize) -> usize {
+    0
+}
+
+/// The allocation behind a buffer, canaries included.
+fn layout(capacity: usize, align: usize) -> Layout {
+    #[cfg(feature = "debug-pool")]
+    let capacity = capacity + front_pad(align) + CANARY_LEN;
+    Layout::from_size_align(capacity, align).expect("invalid buffer layout")
+}
+
+// SAFETY: a PoolBuf owns its allocation outright, just like a Vec<u8>
+unsafe impl Send for PoolBuf {}
+unsafe impl Sync for PoolBuf {}
+
+impl PoolBuf {
+    /// Allocate `capacity` bytes aligned to `align`, which must be a power of two. Nothing is
+    /// initialized yet.
+    pub(crate) fn with_capacity(capacity: usize, align: usize) -> Self {
+        let layout = layout(capacity, align);
+        if capacity == 0 {
+            // never dereferenced, but slices still want it aligned
+            let ptr = NonNull::new(std::ptr::null_mut::<u8>().wrapping_add(align)).unwrap();
+            return Self::from_raw(ptr, capacity, align, None);
+        }
+        // SAFETY: the layout has a non-zero size
+        let ptr =
+            NonNull::new(unsafe { alloc(layout) }).unwrap_or_else(|| handle_alloc_error(layout));
+        // SAFETY: freshly allocated with room for the padding
+        unsafe { Self::from_alloc(ptr, capacity, align, None) }
+    }
+
+    /// Like [`PoolBuf::with_capacity`], but with memory from `source`, or from the global
+    /// allocator if `source` has none to give.
+    pub(crate) fn in_source(capacity: usize, align: usize, source: &Arc<dyn BufferSource>) -> Self {
+        match source.allocate(layout(capacity, align)) {
+            // SAFETY: the source promises an allocation of the size asked for
+            Some(ptr) if capacity != 0 => unsafe {
+                Self::from_alloc(ptr, capacity, align, Some(source.clone()))
+            },
+            _ => Self::with_capacity(capacity, align),
+        }
+    }
+
+    /// Take over an allocation made with [`layout`], arming its canaries.
+    ///
+    /// # Safety
+    ///
+    /// `base` points to a fresh allocation with exactly that layout.
+    unsafe fn from_alloc(
+        base: NonNull<u8>,
+        capacity: usize,
+        align: usize,
+        source: Option<Arc<dyn BufferSource>>,
+    ) -> Self {
+        let buf = Self::from_raw(base.add(front_pad(align)), capacity, align, source);
+        #[cfg(feature = "debug-pool")]
+        {
+            base.as_ptr().write_bytes(CANARY, front_pad(align));
+            buf.ptr
+                .as_ptr()
+                .add(capacity)
+                .write_bytes(CANARY, CANARY_LEN);
+        }
+        buf
+    }
+
+    fn from_raw(
+        ptr: NonNull<u8>,
+        capacity: usize,
+        align: usize,
+        source: Option<Arc<dyn BufferSource>>,
+    ) -> Self {
+        Self {
+            ptr,
+            capacity,
+            len: 0,
+            align,
+            source,
+            #[cfg(feature = "debug-pool")]
+            returned: None,
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn align(&self) -> usize {
+        self.align
+    }
+
+    /// The first `len` bytes (capped at the capacity), zeroing whatever part of them no earlier
+    /// use has initialized.
+    pub(crate) fn init(&mut self, len: usize) -> &mut [u8] {
+        let len = len.min(self.capacity);
+        if self.len < len {
+            // SAFETY: the range lies within the allocation
+            unsafe {
+                self.ptr
+                    .as_ptr()
+                    .add(self.len)
+                    .write_bytes(0, len - self.len)
+            };
+            self.len = len;
+        }
+        &mut self[..len]
+    }
+
+    /// Overwrite everything ever initialized with zeros, in a way the compiler won't optimize out
+    /// even when the buffer is about to be freed.
+    pub(crate) fn zeroize(&mut self) {
+        for byte in self.iter_mut() {
+            // SAFETY: a valid, exclusively borrowed byte
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+        std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+    }
+
+    #[cfg(feature = "tokio")]
+    /// The whole capacity, initialized or not, for readers that can fill uninitialized memory.
+    pub(crate) fn spare_mut(&mut self) -> &mut [std::mem::MaybeUninit<u8>] {
+        // SAFETY: allocated, and MaybeUninit asks nothing more of it
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr().cast(), self.capacity) }
+    }
+
+    #[cfg(feature = "tokio")]
+    /// Record that the first `len` bytes are initialized now, if more than before.
+    ///
+    /// # Safety
+    ///
+    /// They really are, e.g. written through [`PoolBuf::spare_mut`].
+    pub(crate) unsafe fn assume_init(&mut self, len: usize) {
+        self.len = self.len.max(len.min(self.capacity));
+    }
+
+    /// Forget the contents, so the buffer can be refilled with [`PoolBuf::extend_from_slice`].
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Append `data` after the initialized prefix. Panics if it doesn't fit in the capacity.
+    pub(crate) fn extend_from_slice(&mut self, data: &[u8]) {
+        assert!(
+            data.len() <= self.capacity - self.len,
+            "pooled buffer overflow"
+        );
+        // SAFETY: checked to fit, and `data` can't alias a buffer we hold mutably
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.ptr.as_ptr().add(self.len),
+                data.len(),
+            )
+        };
+        self.len += data.len();
+    }
+
+    /// Where the buffer starts, which identifies it while it is allocated.
+    #[cfg(feature = "debug-pool")]
+    pub(crate) fn addr(&self) -> usize {
+        self.ptr.as_ptr() as usize
+    }
+
+    /// Panic if anything wrote past either end of the buffer.
+    #[cfg(feature = "debug-pool")]
+    #[track_caller]
+    fn check_canaries(&self) {
+        if self.capacity == 0 {
+            return;
+        }
+        let pad = front_pad(self.align);
+        // SAFETY: the canaries are allocated and initialized, see `from_alloc`
+        let (front, back) = unsafe {
+            (
+                std::slice::from_raw_parts(self.ptr.as_ptr().sub(pad), pad),
+                std::slice::from_raw_parts(self.ptr.as_ptr().add(self.capacity), CANARY_LEN),
+            )
+        };
+        assert!(
+            front.iter().chain(back).all(|byte| *byte == CANARY),
+            "pooled buffer overrun: something wrote past the ends of a {}-byte buffer",
+            self.capacity,
+        );
+    }
+
+    /// Note that the buffer is going back into a pool.
+    #[cfg(feature = "debug-pool")]
+    #[track_caller]
+    pub(crate) fn check_in(&mut self) {
+        self.check_canaries();
+        assert!(
+            self.returned.is_none(),
+            "pooled buffer returned to its pool twice"
+        );
+        self.returned = Some(checksum(self));
+    }
+
+    /// Note that the buffer is coming out of a pool, panicking if it was written to since it went
+    /// in.
+    #[cfg(feature = "debug-pool")]
+    #[track_caller]
+    pub(crate) fn check_out(&mut self) {
+        self.check_canaries();
+        if let Some(sum) = self.returned.take() {
+            assert!(
+                sum == checksum(self),
+                "pooled buffer written to after it was returned to its pool"
+            );
+        }
+    }
+}
+
+/// FNV-1a, plenty to notice stray writes.
+#[cfg(feature = "debug-pool")]
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl Default for PoolBuf {
+    /// An empty placeholder that owns no memory.
+    fn default() -> Self {
+        Self::with_capacity(0, 1)
+    }
+}
+
+impl std::ops::Deref for PoolBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the first `len` bytes are allocated and initialized
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl std::ops::DerefMut for PoolBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as for `deref`, and we hold the buffer mutably
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for PoolBuf {
+    fn drop(&mut self) {
+        if self.capacity == 0 {
+            return;
+        }
+        #[cfg(feature = "debug-pool")]
+        if !std::thread::panicking() {
+            self.check_canaries();
+        }
+        let layout = layout(self.capacity, self.align);
+        // SAFETY: allocated with exactly this layout, by the source if there is one
+        unsafe {
+            let base = self.ptr.sub(front_pad(self.align));
+            match &self.source {
+                Some(source) => source.recycle(base, layout),
+                None => dealloc(base.as_ptr(), layout),
+            }
+        }
+    }
+}
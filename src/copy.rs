@@ -0,0 +1,964 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures_timer::Delay;
+use futures_util::{
+    future::poll_fn, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt,
+};
+
+use crate::{
+    init_buf,
+    metrics::{self, ActiveCopy},
+    pool::PoolRef,
+    pooled_read_recycled,
+    rate::TokenBucket,
+    retry::Retrying,
+    retry_interrupted,
+    time::Instant,
+    BufGuard, BufPool, CopyError, CopyInterrupted, RetryPolicy,
+};
+
+/// The smallest chunk size accepted by [`PooledCopy::chunk_size`].
+pub const MIN_CHUNK_SIZE: usize = 4 << 10;
+
+/// The largest chunk size accepted by [`PooledCopy::chunk_size`].
+pub const MAX_CHUNK_SIZE: usize = 1 << 20;
+
+/// How many chunks a copy holds at once: read ahead while the writer is busy, then written out
+/// together with one vectored write.
+const MAX_BATCH: usize = 4;
+
+type ChunkFn<'a> = Box<dyn FnMut(&[u8]) + Send + 'a>;
+
+/// One direction of a pooled copy.
+///
+/// Pooled buffers are only taken out while chunks are actually in flight: if the reader isn't ready
+/// the buffer goes straight back, so idle copies hold no memory.
+pub(crate) struct CopyState<'a> {
+    /// Chunks read but not yet fully written, each with its filled length.
+    batch: VecDeque<(BufGuard, usize)>,
+    /// How much of the front chunk has already been written.
+    pos: usize,
+    /// Bytes in `batch` still waiting to be written, and how many there may be at once.
+    in_flight: usize,
+    max_in_flight: usize,
+    /// Where chunk buffers come from.
+    pool: PoolRef,
+    /// How many bytes to read per chunk, if not the pool's buffer size.
+    chunk: Option<usize>,
+    amt: u64,
+    /// How many more bytes may be read from the reader.
+    remaining: u64,
+    read_done: bool,
+    need_flush: bool,
+    /// Called with the running total after each write.
+    progress: Option<Box<dyn FnMut(u64) + Send + 'a>>,
+    /// Called with every chunk as it is read, before it is written.
+    inspect: Option<ChunkFn<'a>>,
+    rate: Option<TokenBucket>,
+    cancel: Option<Pin<Box<dyn Future<Output = ()> + Send + 'a>>>,
+    cancelled: bool,
+    stats: CopyStats,
+    started: Option<Instant>,
+    /// Fail with `TimedOut` once no bytes have moved for this long.
+    idle_timeout: Option<Duration>,
+    idle_timer: Option<Delay>,
+    /// Fires at the deadline for the whole copy, if there is one.
+    deadline: Option<Delay>,
+    retry: Option<Retrying>,
+    /// Counts the copy as active from its first poll until it resolves or is dropped.
+    active: Option<ActiveCopy>,
+}
+
+impl<'a> CopyState<'a> {
+    pub(crate) fn new() -> Self {
+        Self::with_limit(u64::MAX)
+    }
+
+    /// A copy that stops after reading `limit` bytes, leaving the rest in the reader.
+    pub(crate) fn with_limit(limit: u64) -> Self {
+        Self {
+            batch: VecDeque::new(),
+            pos: 0,
+            in_flight: 0,
+            max_in_flight: usize::MAX,
+            pool: PoolRef::Global,
+            chunk: None,
+            amt: 0,
+            remaining: limit,
+            read_done: limit == 0,
+            need_flush: false,
+            progress: None,
+            inspect: None,
+            rate: None,
+            cancel: None,
+            cancelled: false,
+            stats: CopyStats::default(),
+            started: None,
+            idle_timeout: None,
+            idle_timer: None,
+            deadline: None,
+            retry: None,
+            active: None,
+        }
+    }
+
+    /// Flush what has been written so far, so it doesn't sit in a buffered writer while we wait.
+    fn poll_flush_idle<W: AsyncWrite + ?Sized>(
+        &mut self,
+        cx: &mut Context<'_>,
+        writer: Pin<&mut W>,
+    ) -> Poll<Result<(), CopyError>> {
+        if self.need_flush {
+            std::task::ready!(writer.poll_flush(cx)).map_err(CopyError::Flush)?;
+            self.need_flush = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Drive the copy until the reader hits EOF and everything has been written and flushed.
+    pub(crate) fn poll_copy<R: AsyncRead + ?Sized, W: AsyncWrite + ?Sized>(
+        &mut self,
+        cx: &mut Context<'_>,
+        reader: Pin<&mut R>,
+        writer: Pin<&mut W>,
+    ) -> Poll<Result<u64, CopyError>> {
+        let started = *self.started.get_or_insert_with(|| {
+            trace_event!(debug, "pooled copy started");
+            self.active = Some(ActiveCopy::new());
+            Instant::now()
+        });
+        let before = (self.amt, self.stats.chunks);
+        let res = self.poll_copy_retrying(cx, reader, writer);
+        self.stats.bytes = self.amt;
+        self.stats.elapsed = started.elapsed();
+        if res.is_ready() {
+            self.active = None;
+        }
+        #[cfg(feature = "tracing")]
+        match &res {
+            Poll::Ready(Ok(_)) => tracing::debug!(
+                bytes = self.stats.bytes,
+                chunks = self.stats.chunks,
+                elapsed = ?self.stats.elapsed,
+                "pooled copy finished"
+            ),
+            Poll::Ready(Err(err)) => {
+                tracing::debug!(bytes = self.stats.bytes, error = %err, "pooled copy failed")
+            }
+            Poll::Pending => {}
+        }
+        if res.is_pending() {
+            if let Some(timeout) = self.idle_timeout {
+                let timer = self.idle_timer.get_or_insert_with(|| Delay::new(timeout));
+                if before != (self.amt, self.stats.chunks) {
+                    timer.reset(timeout);
+                }
+                if Pin::new(timer).poll(cx).is_ready() {
+                    return Poll::Ready(Err(self.timed_out()));
+                }
+            }
+            if let Some(deadline) = &mut self.deadline {
+                if Pin::new(deadline).poll(cx).is_ready() {
+                    return Poll::Ready(Err(self.timed_out()));
+                }
+            }
+        }
+        res
+    }
+
+    /// Drive the copy, going again after the backoff whenever it fails with an error the retry
+    /// policy covers. Buffered chunks stay in the batch meanwhile.
+    fn poll_copy_retrying<R: AsyncRead + ?Sized, W: AsyncWrite + ?Sized>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut R>,
+        mut writer: Pin<&mut W>,
+    ) -> Poll<Result<u64, CopyError>> {
+        loop {
+            if let Some(retry) = &mut self.retry {
+                std::task::ready!(retry.poll_wait(cx));
+            }
+            let before = (self.amt, self.stats.chunks);
+            let res = self.poll_copy_inner(cx, reader.as_mut(), writer.as_mut());
+            let Some(retry) = &mut self.retry else {
+                return res;
+            };
+            if before != (self.amt, self.stats.chunks) {
+                retry.progressed();
+            }
+            match res {
+                Poll::Ready(Err(err)) if retry.schedule(err.io_error()) => {}
+                res => return res,
+            }
+        }
+    }
+
+    /// A `TimedOut` error blaming whichever side the copy was stuck waiting on.
+    fn timed_out(&self) -> CopyError {
+        trace_event!(debug, bytes = self.amt, "pooled copy timed out");
+        let err = std::io::ErrorKind::TimedOut.into();
+        if !self.batch.is_empty() {
+            CopyError::Write(err)
+        } else if self.read_done {
+            CopyError::Flush(err)
+        } else {
+            CopyError::Read(err)
+        }
+    }
+
+    fn poll_copy_inner<R: AsyncRead + ?Sized, W: AsyncWrite + ?Sized>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut R>,
+        mut writer: Pin<&mut W>,
+    ) -> Poll<Result<u64, CopyError>> {
+        loop {
+            // Read ahead while there is room, so the next chunks are already in hand while the
+            // writer works through earlier ones, and a busy stream gets written out in batches
+            // instead of one write per chunk.
+            while !self.read_done
+                && self.batch.len() < MAX_BATCH
+                && self.in_flight < self.max_in_flight
+            {
+                if self.poll_read_chunk(cx, reader.as_mut())?.is_pending() {
+                    break;
+                }
+            }
+            if self.batch.is_empty() {
+                if self.read_done {
+                    std::task::ready!(writer.as_mut().poll_flush(cx)).map_err(CopyError::Flush)?;
+                    self.need_flush = false;
+                    return Poll::Ready(Ok(self.amt));
+                }
+                std::task::ready!(self.poll_flush_idle(cx, writer.as_mut()))?;
+                return Poll::Pending;
+            }
+
+            std::task::ready!(self.poll_write_batch(cx, writer.as_mut()))?;
+            if let Some(progress) = &mut self.progress {
+                progress(self.amt);
+            }
+        }
+    }
+
+    /// Read one chunk onto the end of the batch, or mark the reader done on EOF or cancellation.
+    fn poll_read_chunk<R: AsyncRead + ?Sized>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut R>,
+    ) -> Poll<Result<(), CopyError>> {
+        if let Some(cancel) = &mut self.cancel {
+            if cancel.as_mut().poll(cx).is_ready() {
+                self.cancel = None;
+                self.cancelled = true;
+                self.read_done = true;
+                return Poll::Ready(Ok(()));
+            }
+        }
+        let chunk = self.chunk.unwrap_or_else(|| self.pool.get().buf_size());
+        let mut want = usize::try_from(self.remaining)
+            .map_or(chunk, |r| r.min(chunk))
+            .min(self.max_in_flight - self.in_flight);
+        if let Some(rate) = &mut self.rate {
+            want = std::task::ready!(rate.poll_available(cx, want));
+        }
+        let (mut free_buf, reused) = std::task::ready!(self.pool.poll_acquire(cx, chunk));
+        let read =
+            retry_interrupted(|| reader.as_mut().poll_read(cx, init_buf(&mut free_buf, want)));
+        // a buffer that goes straight back because the reader wasn't ready doesn't count
+        if let Poll::Ready(Ok(_)) = read {
+            if reused {
+                self.stats.pool_hits += 1;
+            } else {
+                self.stats.pool_misses += 1;
+            }
+        }
+        match read {
+            Poll::Ready(Ok(0)) => self.read_done = true,
+            Poll::Ready(Ok(n)) => {
+                trace_event!(trace, len = n, "pooled copy read a chunk");
+                if let Some(inspect) = &mut self.inspect {
+                    inspect(&free_buf[..n]);
+                }
+                self.batch.push_back((free_buf, n));
+                self.in_flight += n;
+                self.remaining -= n as u64;
+                self.read_done = self.remaining == 0;
+                self.stats.record_chunk(n);
+                if let Some(rate) = &mut self.rate {
+                    rate.consume(n);
+                }
+            }
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(CopyError::Read(err))),
+            Poll::Pending => return Poll::Pending,
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Take out the chunks that were read but not yet written, returning their buffers to the pool.
+    pub(crate) fn take_unwritten(&mut self) -> Bytes {
+        let mut tail = BytesMut::new();
+        let mut pos = std::mem::take(&mut self.pos);
+        self.in_flight = 0;
+        for (buf, len) in self.batch.drain(..) {
+            tail.extend_from_slice(&buf[pos..len]);
+            pos = 0;
+        }
+        tail.freeze()
+    }
+
+    /// Submit the batched chunks with a single write, returning fully written ones to the pool.
+    fn poll_write_batch<W: AsyncWrite + ?Sized>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut writer: Pin<&mut W>,
+    ) -> Poll<Result<(), CopyError>> {
+        let mut slices = [IoSlice::new(&[]); MAX_BATCH];
+        for (i, (buf, len)) in self.batch.iter().enumerate() {
+            let start = if i == 0 { self.pos } else { 0 };
+            slices[i] = IoSlice::new(&buf[start..*len]);
+        }
+        let count = self.batch.len();
+        let mut n = std::task::ready!(if count == 1 {
+            writer.as_mut().poll_write(cx, &slices[0])
+        } else {
+            writer.as_mut().poll_write_vectored(cx, &slices[..count])
+        })
+        .map_err(CopyError::Write)?;
+        if n == 0 {
+            return Poll::Ready(Err(CopyError::Write(std::io::ErrorKind::WriteZero.into())));
+        }
+        self.amt += n as u64;
+        metrics::copied(n);
+        self.in_flight -= n;
+        self.need_flush = true;
+        while let Some((_, len)) = self.batch.front() {
+            let left = len - self.pos;
+            if n < left {
+                self.pos += n;
+                break;
+            }
+            n -= left;
+            self.pos = 0;
+            self.batch.pop_front();
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Copy everything from `reader` into `writer` through pooled buffers, returning the byte count.
+///
+/// Like [`crate::pooled_read`], no buffer is held while the reader is blocked. Use [`PooledCopy`]
+/// for more control over the copy.
+pub async fn pooled_copy(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<u64, CopyError> {
+    PooledCopy::new(reader, writer).await
+}
+
+/// Copy at most `n` bytes from `reader` into `writer`, returning how many were actually copied.
+///
+/// Nothing past those `n` bytes is read, so the reader is left positioned right after them.
+pub async fn pooled_copy_n(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+    n: u64,
+) -> Result<u64, CopyError> {
+    PooledCopy::new(reader, writer).limit(n).await
+}
+
+/// Like [`pooled_copy`], but reading `chunk` bytes at a time instead of the pool's buffer size.
+///
+/// `chunk` is clamped to [`MIN_CHUNK_SIZE`]..=[`MAX_CHUNK_SIZE`]. Bigger chunks mean fewer syscalls
+/// for large sequential copies; smaller ones get data moving sooner on latency-sensitive paths.
+pub async fn pooled_copy_with_chunk_size(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+    chunk: usize,
+) -> Result<u64, CopyError> {
+    PooledCopy::new(reader, writer).chunk_size(chunk).await
+}
+
+/// Like [`pooled_copy`], but throttled to roughly `bytes_per_sec` with a token bucket.
+pub async fn pooled_copy_limited(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+    bytes_per_sec: u64,
+) -> Result<u64, CopyError> {
+    PooledCopy::new(reader, writer)
+        .rate_limit(bytes_per_sec)
+        .await
+}
+
+/// Like [`pooled_copy`], but fails with `TimedOut` if it isn't done by `deadline`, as with
+/// [`PooledCopy::deadline`].
+pub async fn pooled_copy_with_deadline(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+    deadline: Instant,
+) -> Result<u64, CopyError> {
+    PooledCopy::new(reader, writer).deadline(deadline).await
+}
+
+/// Like [`pooled_copy`], but retrying transient failures as `policy` says, as with
+/// [`PooledCopy::retry`].
+pub async fn pooled_copy_with_retry(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+    policy: RetryPolicy,
+) -> Result<u64, CopyError> {
+    PooledCopy::new(reader, writer).retry(policy).await
+}
+
+/// Like [`pooled_copy`], but hands every chunk to `f` on its way through, e.g. to feed a hasher
+/// so the transfer can be verified without reading the data a second time.
+pub async fn pooled_copy_inspect(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+    f: impl FnMut(&[u8]) + Send,
+) -> Result<u64, CopyError> {
+    PooledCopy::new(reader, writer).inspect_chunks(f).await
+}
+
+/// Like [`pooled_copy`], but also hashes the copied bytes with `D`, resolving to the byte count
+/// and the final digest.
+#[cfg(feature = "digest")]
+pub async fn pooled_copy_digest<D: digest::Digest + Send>(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<(u64, digest::Output<D>), CopyError> {
+    let mut hasher = D::new();
+    let n = pooled_copy_inspect(reader, writer, |chunk| hasher.update(chunk)).await?;
+    Ok((n, hasher.finalize()))
+}
+
+/// Copy several readers one after another into a single writer, returning each one's byte count.
+///
+/// Every source is drained to EOF before the next one starts, all through the shared pool, so this
+/// holds no more memory than a single [`pooled_copy`].
+pub async fn pooled_copy_concat<R: AsyncRead + Unpin>(
+    readers: impl IntoIterator<Item = R>,
+    mut writer: impl AsyncWrite + Unpin,
+) -> Result<Vec<u64>, CopyError> {
+    let mut counts = Vec::new();
+    for reader in readers {
+        counts.push(PooledCopy::new(reader, &mut writer).await?);
+    }
+    Ok(counts)
+}
+
+/// Copy from an already-buffered reader by writing straight out of its internal buffer.
+///
+/// This skips the pooled buffer entirely, saving a memcpy per chunk when the reader buffers anyway.
+pub async fn pooled_copy_buf(
+    mut reader: impl AsyncBufRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+) -> Result<u64, CopyError> {
+    let mut total = 0u64;
+    loop {
+        let buf = match reader.fill_buf().await {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(CopyError::Read(err)),
+        };
+        if buf.is_empty() {
+            writer.flush().await.map_err(CopyError::Flush)?;
+            return Ok(total);
+        }
+        let n = writer.write(buf).await.map_err(CopyError::Write)?;
+        if n == 0 {
+            return Err(CopyError::Write(std::io::ErrorKind::WriteZero.into()));
+        }
+        reader.consume_unpin(n);
+        total += n as u64;
+    }
+}
+
+/// Relay bytes up to and including the first occurrence of `delim`, then stop.
+///
+/// Returns how many bytes were written along with whatever was read past the delimiter, which the
+/// caller should treat as the start of the rest of the stream (e.g. the body after `\r\n\r\n`).
+/// Fails with a [`CopyError::Read`] of kind `UnexpectedEof` if the reader ends before the delimiter
+/// shows up.
+pub async fn pooled_copy_until(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    delim: &[u8],
+) -> Result<(u64, Bytes), CopyError> {
+    if delim.is_empty() {
+        return Err(CopyError::Read(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "delimiter must not be empty",
+        )));
+    }
+    let finder = memchr::memmem::Finder::new(delim);
+    // the tail of what was already written, in case the delimiter straddles two chunks
+    let mut carry: Vec<u8> = Vec::with_capacity(delim.len() * 2);
+    let mut total = 0u64;
+    loop {
+        let chunk = pooled_read_recycled(&mut reader)
+            .await
+            .map_err(CopyError::Read)?;
+        if chunk.is_empty() {
+            return Err(CopyError::Read(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+        let carried = carry.len();
+        carry.extend_from_slice(&chunk[..chunk.len().min(delim.len() - 1)]);
+        let found = match finder.find(&carry) {
+            Some(idx) => Some(idx + delim.len() - carried),
+            None => finder.find(&chunk).map(|idx| idx + delim.len()),
+        };
+        let end = found.unwrap_or(chunk.len());
+        writer
+            .write_all(&chunk[..end])
+            .await
+            .map_err(CopyError::Write)?;
+        total += end as u64;
+        if found.is_some() {
+            writer.flush().await.map_err(CopyError::Flush)?;
+            return Ok((total, Bytes::copy_from_slice(&chunk[end..])));
+        }
+        if chunk.len() >= delim.len() - 1 {
+            carry.clear();
+            carry.extend_from_slice(&chunk[chunk.len() - (delim.len() - 1)..]);
+        } else {
+            carry.drain(..carry.len() - (delim.len() - 1).min(carry.len()));
+        }
+    }
+}
+
+/// Like [`pooled_copy`], but passes every chunk through `f` before writing it.
+///
+/// `f` may return the chunk as-is (`Cow::Borrowed`, written straight from the pooled buffer) or a
+/// rewritten version. Resolves to the number of bytes written, which may differ from the number
+/// read.
+pub async fn pooled_copy_map(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    mut f: impl FnMut(&[u8]) -> Cow<'_, [u8]>,
+) -> Result<u64, CopyError> {
+    let mut total = 0u64;
+    loop {
+        let chunk = pooled_read_recycled(&mut reader)
+            .await
+            .map_err(CopyError::Read)?;
+        if chunk.is_empty() {
+            writer.flush().await.map_err(CopyError::Flush)?;
+            return Ok(total);
+        }
+        let mapped = f(&chunk);
+        writer.write_all(&mapped).await.map_err(CopyError::Write)?;
+        total += mapped.len() as u64;
+    }
+}
+
+/// Detailed accounting for a finished copy, from [`pooled_copy_stats`] or [`PooledCopy::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CopyStats {
+    /// Total bytes written.
+    pub bytes: u64,
+    /// Number of non-empty chunks read.
+    pub chunks: u64,
+    /// Size of the smallest non-empty chunk read, or zero if there were none.
+    pub min_chunk: usize,
+    /// Size of the largest chunk read.
+    pub max_chunk: usize,
+    /// Reads whose buffer was reused from the pool rather than freshly allocated.
+    pub pool_hits: u64,
+    /// Reads whose buffer had to be freshly allocated because the pool had none idle.
+    pub pool_misses: u64,
+    /// Wall-clock time from the first poll to the last.
+    pub elapsed: Duration,
+}
+
+impl CopyStats {
+    /// The mean chunk size, or zero if nothing was read.
+    pub fn mean_chunk(&self) -> u64 {
+        self.bytes.checked_div(self.chunks).unwrap_or(0)
+    }
+
+    fn record_chunk(&mut self, n: usize) {
+        self.min_chunk = if self.chunks == 0 {
+            n
+        } else {
+            self.min_chunk.min(n)
+        };
+        self.max_chunk = self.max_chunk.max(n);
+        self.chunks += 1;
+    }
+}
+
+/// Like [`pooled_copy`], but resolves to a full [`CopyStats`] instead of just the byte count.
+pub async fn pooled_copy_stats(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<CopyStats, CopyError> {
+    let mut copy = PooledCopy::new(reader, writer);
+    (&mut copy).await?;
+    Ok(*copy.stats())
+}
+
+/// How a [`pooled_copy_cancellable`] ended, with the number of bytes copied either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyOutcome {
+    Completed(u64),
+    Cancelled(u64),
+}
+
+/// Like [`pooled_copy`], but stops gracefully once `signal` resolves.
+///
+/// On cancellation the chunk already in flight is still written out and the writer flushed, so the
+/// returned count is exactly what reached the writer.
+pub async fn pooled_copy_cancellable(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+    signal: impl Future<Output = ()> + Send,
+) -> Result<CopyOutcome, CopyError> {
+    let mut copy = PooledCopy::new(reader, writer).cancel_on(signal);
+    let n = (&mut copy).await?;
+    if copy.is_cancelled() {
+        Ok(CopyOutcome::Cancelled(n))
+    } else {
+        Ok(CopyOutcome::Completed(n))
+    }
+}
+
+/// A configurable [`pooled_copy`], resolving to the number of bytes copied when awaited.
+pub struct PooledCopy<'a, R, W> {
+    reader: R,
+    writer: W,
+    state: CopyState<'a>,
+}
+
+impl<'a, R: AsyncRead + Unpin, W: AsyncWrite + Unpin> PooledCopy<'a, R, W> {
+    /// Set up a copy of everything in `reader` into `writer`.
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            state: CopyState::new(),
+        }
+    }
+
+    /// Stop after copying `n` bytes, as in [`pooled_copy_n`].
+    pub fn limit(mut self, n: u64) -> Self {
+        self.state.remaining = n;
+        self.state.read_done = n == 0;
+        self
+    }
+
+    /// Take chunk buffers from `pool` instead of the global pool.
+    pub fn pool(mut self, pool: &BufPool) -> Self {
+        self.state.pool = PoolRef::Owned(pool.clone());
+        self
+    }
+
+    /// Read up to `chunk` bytes at a time, as in [`pooled_copy_with_chunk_size`].
+    pub fn chunk_size(mut self, chunk: usize) -> Self {
+        self.state.chunk = Some(chunk.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE));
+        self
+    }
+
+    /// Never hold more than `bytes` (at least 1) that were read but not yet written.
+    ///
+    /// The reader then waits on the writer instead of queueing up more pooled chunks, so a fast
+    /// reader and a slow writer can't make the copy's memory balloon.
+    pub fn max_in_flight(mut self, bytes: usize) -> Self {
+        self.state.max_in_flight = bytes.max(1);
+        self
+    }
+
+    /// Throttle the copy to roughly `bytes_per_sec`, sleeping between chunks as needed.
+    pub fn rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.state.rate = Some(TokenBucket::new(bytes_per_sec));
+        self
+    }
+
+    /// Fail with `TimedOut` if nothing is read or written for `timeout`, so a stalled peer can't pin
+    /// the copy forever.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.state.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Fail with `TimedOut` if the whole copy isn't done by `deadline`, however steadily it's
+    /// moving. The deadline is a `std::time::Instant`, or a `web_time::Instant` on browser wasm.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        let left = deadline.saturating_duration_since(Instant::now());
+        self.state.deadline = Some(Delay::new(left));
+        self
+    }
+
+    /// Retry reads and writes that fail with an error `policy` covers, after a backoff, keeping
+    /// the chunks read so far.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.state.retry = Some(Retrying::new(policy));
+        self
+    }
+
+    /// Stop gracefully once `signal` resolves, as in [`pooled_copy_cancellable`].
+    pub fn cancel_on(mut self, signal: impl Future<Output = ()> + Send + 'a) -> Self {
+        self.state.cancel = Some(Box::pin(signal));
+        self
+    }
+
+    /// Statistics for the copy so far; complete once it has resolved.
+    pub fn stats(&self) -> &CopyStats {
+        &self.state.stats
+    }
+
+    /// Whether the copy stopped because its [`PooledCopy::cancel_on`] signal fired.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled
+    }
+
+    /// Call `f` with every chunk as it is read, before it is written out, as in
+    /// [`pooled_copy_inspect`]. `f` may itself do pooled reads; the chunk's buffer is taken from
+    /// the pool before `f` runs, so nested reads simply get buffers of their own.
+    pub fn inspect_chunks(mut self, f: impl FnMut(&[u8]) + Send + 'a) -> Self {
+        self.state.inspect = Some(Box::new(f));
+        self
+    }
+
+    /// Call `f` with the running total after each write, e.g. to drive a progress bar.
+    pub fn on_progress(mut self, f: impl FnMut(u64) + Send + 'a) -> Self {
+        self.state.progress = Some(Box::new(f));
+        self
+    }
+}
+
+impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> Future for PooledCopy<'_, R, W> {
+    type Output = Result<u64, CopyError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.state
+            .poll_copy(cx, Pin::new(&mut this.reader), Pin::new(&mut this.writer))
+    }
+}
+
+/// A [`pooled_copy`] that, when it fails, reports exactly how far it got.
+///
+/// The [`CopyInterrupted`] error carries the byte counters and whatever was read but not yet
+/// written, so the transfer can continue on a fresh writer with [`ResumableCopy::resume`] without
+/// losing or duplicating any bytes.
+pub struct ResumableCopy<R, W> {
+    reader: R,
+    writer: W,
+    state: CopyState<'static>,
+    /// Left over from an earlier attempt; written out before anything new is read.
+    pending: Bytes,
+    /// Totals carried over from earlier attempts.
+    read: u64,
+    written: u64,
+}
+
+impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> ResumableCopy<R, W> {
+    /// Set up a copy of everything in `reader` into `writer`.
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            state: CopyState::new(),
+            pending: Bytes::new(),
+            read: 0,
+            written: 0,
+        }
+    }
+
+    /// Continue an interrupted copy, first writing its unwritten tail to `writer`.
+    ///
+    /// `reader` should be the one the interrupted copy was reading, still positioned where it left
+    /// off; the counters keep running from where `from` stopped.
+    pub fn resume(reader: R, writer: W, from: CopyInterrupted) -> Self {
+        Self {
+            reader,
+            writer,
+            state: CopyState::new(),
+            pending: from.unwritten,
+            read: from.bytes_read,
+            written: from.bytes_written,
+        }
+    }
+
+    /// Take back the reader and writer, e.g. to pass the reader to [`ResumableCopy::resume`].
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+
+    fn interrupted(&mut self, error: CopyError) -> CopyInterrupted {
+        let written = self.written + self.state.amt;
+        let tail = self.state.take_unwritten();
+        let read = self.read + self.state.amt + tail.len() as u64;
+        let unwritten = if self.pending.is_empty() {
+            tail
+        } else {
+            let mut joined = BytesMut::from(&std::mem::take(&mut self.pending)[..]);
+            joined.extend_from_slice(&tail);
+            joined.freeze()
+        };
+        CopyInterrupted {
+            error,
+            bytes_read: read,
+            bytes_written: written,
+            unwritten,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> Future for ResumableCopy<R, W> {
+    type Output = Result<u64, CopyInterrupted>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        while !this.pending.is_empty() {
+            match Pin::new(&mut this.writer).poll_write(cx, &this.pending) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(
+                        this.interrupted(CopyError::Write(std::io::ErrorKind::WriteZero.into()))
+                    ))
+                }
+                Poll::Ready(Ok(n)) => {
+                    this.pending.advance(n);
+                    this.written += n as u64;
+                }
+                Poll::Ready(Err(err)) => {
+                    return Poll::Ready(Err(this.interrupted(CopyError::Write(err))))
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        match this
+            .state
+            .poll_copy(cx, Pin::new(&mut this.reader), Pin::new(&mut this.writer))
+        {
+            Poll::Ready(Ok(n)) => Poll::Ready(Ok(this.written + n)),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(this.interrupted(err))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// What [`pooled_copy_bidirectional_with`] does once one direction reaches EOF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HalfClose {
+    /// Close the writer on the far side of the finished direction, while the other direction keeps
+    /// flowing until its own EOF.
+    PropagateShutdown,
+    /// Leave the writers open and keep relaying the other direction; nothing is ever closed.
+    KeepOpen,
+    /// Stop reading the other direction too, then close both writers once their in-flight data is
+    /// written out.
+    CloseBoth,
+}
+
+/// One half of a bidirectional copy.
+struct Direction {
+    state: CopyState<'static>,
+    phase: Phase,
+    /// Whether to close the writer once the reader is done.
+    close: bool,
+}
+
+enum Phase {
+    Copying,
+    ShuttingDown,
+    Done,
+}
+
+impl Direction {
+    fn new(close: bool) -> Self {
+        Self {
+            state: CopyState::new(),
+            phase: Phase::Copying,
+            close,
+        }
+    }
+
+    /// Stop reading, letting what was already read drain. Returns whether this changed anything.
+    fn stop(&mut self) -> bool {
+        let stopping = matches!(self.phase, Phase::Copying) && !self.state.read_done;
+        self.state.read_done = true;
+        stopping
+    }
+
+    fn poll<R: AsyncRead + Unpin + ?Sized, W: AsyncWrite + Unpin + ?Sized>(
+        &mut self,
+        cx: &mut Context<'_>,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Poll<Result<u64, CopyError>> {
+        loop {
+            match self.phase {
+                Phase::Copying => {
+                    std::task::ready!(self.state.poll_copy(
+                        cx,
+                        Pin::new(&mut *reader),
+                        Pin::new(&mut *writer)
+                    ))?;
+                    self.phase = if self.close {
+                        Phase::ShuttingDown
+                    } else {
+                        Phase::Done
+                    };
+                }
+                Phase::ShuttingDown => {
+                    std::task::ready!(Pin::new(&mut *writer).poll_close(cx))
+                        .map_err(CopyError::Flush)?;
+                    self.phase = Phase::Done;
+                }
+                Phase::Done => return Poll::Ready(Ok(self.state.amt)),
+            }
+        }
+    }
+}
+
+/// Pump data both ways between two duplex streams until both directions reach EOF.
+///
+/// When one side's reader finishes, the other side's writer is closed so the half-close
+/// propagates, while the opposite direction keeps flowing. Returns `(a_to_b, b_to_a)` byte counts.
+pub async fn pooled_copy_bidirectional<A, B>(a: &mut A, b: &mut B) -> Result<(u64, u64), CopyError>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    pooled_copy_bidirectional_with(a, b, HalfClose::PropagateShutdown).await
+}
+
+/// Like [`pooled_copy_bidirectional`], but with a choice of what happens on a half-close.
+pub async fn pooled_copy_bidirectional_with<A, B>(
+    a: &mut A,
+    b: &mut B,
+    policy: HalfClose,
+) -> Result<(u64, u64), CopyError>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let close = policy != HalfClose::KeepOpen;
+    let mut a_to_b = Direction::new(close);
+    let mut b_to_a = Direction::new(close);
+    poll_fn(|cx| loop {
+        let ab = a_to_b.poll(cx, a, b)?;
+        let ba = b_to_a.poll(cx, b, a)?;
+        if let (Poll::Ready(ab), Poll::Ready(ba)) = (ab, ba) {
+            return Poll::Ready(Ok((ab, ba)));
+        }
+        if policy == HalfClose::CloseBoth
+            && (ab.is_ready() || ba.is_ready())
+            && (a_to_b.stop() | b_to_a.stop())
+        {
+            continue;
+        }
+        return Poll::Pending;
+    })
+    .await
+}
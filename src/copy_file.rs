@@ -0,0 +1,47 @@
+use std::fs::File;
+use std::os::fd::AsRawFd;
+
+use crate::{pooled_copy_sync, sys::retry_eintr, CopyError};
+
+/// How much to ask the kernel for per `copy_file_range` call.
+const COPY_CHUNK: usize = 1 << 30;
+
+/// Copy `src` from its current position to EOF into `dst` at its current position, using
+/// `copy_file_range` so data never enters userspace and reflink-capable filesystems can share
+/// extents. Returns the number of bytes copied.
+///
+/// Falls back to a pooled read/write loop where the kernel can't do the copy (e.g. across
+/// filesystems on older kernels). Like all regular-file I/O this blocks, so call it from a blocking
+/// thread in async code.
+pub fn pooled_copy_file(src: &File, dst: &File) -> Result<u64, std::io::Error> {
+    let mut total = 0u64;
+    loop {
+        let res = retry_eintr(|| unsafe {
+            libc::copy_file_range(
+                src.as_raw_fd(),
+                std::ptr::null_mut(),
+                dst.as_raw_fd(),
+                std::ptr::null_mut(),
+                COPY_CHUNK,
+                0,
+            ) as isize
+        });
+        match res {
+            Ok(0) => return Ok(total),
+            Ok(n) => total += n as u64,
+            Err(err)
+                if matches!(
+                    err.raw_os_error(),
+                    Some(libc::EXDEV | libc::ENOSYS | libc::EINVAL | libc::EOPNOTSUPP)
+                ) =>
+            {
+                return Ok(total + copy_userspace(src, dst)?);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn copy_userspace(src: &File, dst: &File) -> Result<u64, std::io::Error> {
+    pooled_copy_sync(src, dst).map_err(CopyError::into_io_error)
+}
@@ -0,0 +1,132 @@
+/// Error payload used when a read would grow past its configured cap.
+///
+/// It is always wrapped in an `std::io::Error` of kind `InvalidData`; use
+/// [`LimitExceeded::is`] to tell it apart from other failures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub limit: usize,
+}
+
+impl LimitExceeded {
+    /// Whether an I/O error was caused by a cap being exceeded.
+    pub fn is(err: &std::io::Error) -> bool {
+        err.get_ref().is_some_and(|e| e.is::<LimitExceeded>())
+    }
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "read exceeded the limit of {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl From<LimitExceeded> for std::io::Error {
+    fn from(value: LimitExceeded) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, value)
+    }
+}
+
+/// The error type of the copy family, telling apart which side of the copy failed.
+///
+/// It converts into the underlying `std::io::Error`, so `?` still works in functions returning
+/// plain I/O errors.
+#[derive(Debug)]
+pub enum CopyError {
+    /// Reading from the source failed.
+    Read(std::io::Error),
+    /// Writing to the destination failed.
+    Write(std::io::Error),
+    /// Flushing or closing the destination failed.
+    Flush(std::io::Error),
+}
+
+impl CopyError {
+    /// The underlying I/O error, whichever side it came from.
+    pub fn io_error(&self) -> &std::io::Error {
+        match self {
+            CopyError::Read(err) | CopyError::Write(err) | CopyError::Flush(err) => err,
+        }
+    }
+
+    /// Take out the underlying I/O error.
+    pub fn into_io_error(self) -> std::io::Error {
+        match self {
+            CopyError::Read(err) | CopyError::Write(err) | CopyError::Flush(err) => err,
+        }
+    }
+
+    /// Shorthand for the kind of the underlying I/O error.
+    pub fn kind(&self) -> std::io::ErrorKind {
+        self.io_error().kind()
+    }
+}
+
+impl std::fmt::Display for CopyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CopyError::Read(err) => write!(f, "copy failed reading: {err}"),
+            CopyError::Write(err) => write!(f, "copy failed writing: {err}"),
+            CopyError::Flush(err) => write!(f, "copy failed flushing: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for CopyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.io_error())
+    }
+}
+
+impl From<CopyError> for std::io::Error {
+    fn from(value: CopyError) -> Self {
+        value.into_io_error()
+    }
+}
+
+/// Why a [`crate::ResumableCopy`] stopped, with enough bookkeeping to pick the transfer back up.
+#[derive(Debug)]
+pub struct CopyInterrupted {
+    /// The read or write failure that stopped the copy.
+    pub error: CopyError,
+    /// Everything taken from the reader so far, across all attempts.
+    pub bytes_read: u64,
+    /// Everything the writer accepted so far, across all attempts.
+    pub bytes_written: u64,
+    /// The bytes read but never accepted by the writer, i.e. `bytes_read - bytes_written` of them.
+    pub unwritten: bytes::Bytes,
+}
+
+impl std::fmt::Display for CopyInterrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "copy interrupted after writing {} bytes: {}",
+            self.bytes_written, self.error
+        )
+    }
+}
+
+impl std::error::Error for CopyInterrupted {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<CopyInterrupted> for std::io::Error {
+    fn from(value: CopyInterrupted) -> Self {
+        std::io::Error::new(value.error.kind(), value)
+    }
+}
+
+/// Reject a zero limit on reads whose empty result would otherwise be mistaken for EOF.
+pub(crate) fn check_limit(limit: usize) -> Result<(), std::io::Error> {
+    if limit == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "read limit must be nonzero",
+        ));
+    }
+    Ok(())
+}
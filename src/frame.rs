@@ -0,0 +1,354 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::{future::poll_fn, AsyncRead, AsyncWrite, Sink, Stream};
+
+use crate::{
+    init_buf, poll_pooled_read, pool::PoolRef, pooled_write_vectored, retry_interrupted, BufGuard,
+    BufPool, LimitExceeded, PooledBufWriter, PooledBytes,
+};
+
+/// The largest frame accepted unless configured otherwise.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 8 << 20;
+
+/// How wide the length prefix of a frame is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderWidth {
+    U16,
+    U32,
+    U64,
+}
+
+impl HeaderWidth {
+    fn bytes(self) -> usize {
+        match self {
+            HeaderWidth::U16 => 2,
+            HeaderWidth::U32 => 4,
+            HeaderWidth::U64 => 8,
+        }
+    }
+}
+
+/// The byte order of the length prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+/// The layout of length-prefixed frames, for [`pooled_read_frame`] and friends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameConfig {
+    pub header_width: HeaderWidth,
+    pub endian: Endian,
+    /// Frames longer than this fail with [`LimitExceeded`] on either side, so a corrupt or hostile
+    /// length can't make the reader allocate gigabytes.
+    pub max_frame_size: usize,
+}
+
+impl Default for FrameConfig {
+    /// Big-endian `u32` lengths, and frames of up to [`DEFAULT_MAX_FRAME_SIZE`].
+    fn default() -> Self {
+        Self {
+            header_width: HeaderWidth::U32,
+            endian: Endian::Big,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+}
+
+impl FrameConfig {
+    /// The header for a frame of `len` bytes, left-aligned in an 8-byte array.
+    fn encode(&self, len: usize) -> Result<[u8; 8], std::io::Error> {
+        if len > self.max_frame_size {
+            return Err(LimitExceeded {
+                limit: self.max_frame_size,
+            }
+            .into());
+        }
+        let width = self.header_width.bytes();
+        let len = len as u64;
+        if width < 8 && len >> (width * 8) != 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "frame too long for its length header",
+            ));
+        }
+        let mut header = [0u8; 8];
+        match self.endian {
+            Endian::Big => header[..width].copy_from_slice(&len.to_be_bytes()[8 - width..]),
+            Endian::Little => header[..width].copy_from_slice(&len.to_le_bytes()[..width]),
+        }
+        Ok(header)
+    }
+
+    fn decode(&self, header: &[u8]) -> Result<usize, std::io::Error> {
+        let mut bytes = [0u8; 8];
+        let len = match self.endian {
+            Endian::Big => {
+                bytes[8 - header.len()..].copy_from_slice(header);
+                u64::from_be_bytes(bytes)
+            }
+            Endian::Little => {
+                bytes[..header.len()].copy_from_slice(header);
+                u64::from_le_bytes(bytes)
+            }
+        };
+        match usize::try_from(len) {
+            Ok(len) if len <= self.max_frame_size => Ok(len),
+            _ => Err(LimitExceeded {
+                limit: self.max_frame_size,
+            }
+            .into()),
+        }
+    }
+}
+
+/// Read one length-prefixed frame, returning `None` on a clean EOF before its header.
+///
+/// Frames that fit the global pool's largest size class are read straight into a pooled buffer,
+/// which the returned `Bytes` then holds on to; bigger ones are collected into a fresh allocation.
+/// No buffer is held while waiting for a header. Fails with `UnexpectedEof` if the stream ends
+/// partway through a frame.
+pub async fn pooled_read_frame(
+    mut rdr: impl AsyncRead + Unpin,
+    config: &FrameConfig,
+) -> Result<Option<Bytes>, std::io::Error> {
+    let mut state = FrameState::default();
+    poll_fn(|cx| state.poll_frame(cx, Pin::new(&mut rdr), config, &PoolRef::Global)).await
+}
+
+/// Write `frame` with its length prefix, in one write where the writer supports vectored writes.
+///
+/// The writer itself is not flushed.
+pub async fn pooled_write_frame(
+    writer: impl AsyncWrite + Unpin,
+    frame: &[u8],
+    config: &FrameConfig,
+) -> Result<(), std::io::Error> {
+    let header = config.encode(frame.len())?;
+    let header = &header[..config.header_width.bytes()];
+    pooled_write_vectored(writer, &[header, frame]).await?;
+    Ok(())
+}
+
+/// Stream the length-prefixed frames of `reader`, as read by [`pooled_read_frame`].
+///
+/// The stream ends at a clean EOF between frames, or after yielding an error.
+pub fn pooled_frames<R: AsyncRead + Unpin>(reader: R, config: FrameConfig) -> PooledFrames<R> {
+    PooledFrames {
+        reader,
+        config,
+        pool: PoolRef::Global,
+        state: FrameState::default(),
+        done: false,
+    }
+}
+
+/// The stream returned by [`pooled_frames`].
+pub struct PooledFrames<R> {
+    reader: R,
+    config: FrameConfig,
+    pool: PoolRef,
+    state: FrameState,
+    done: bool,
+}
+
+impl<R> PooledFrames<R> {
+    /// Read frames into buffers from `pool` instead of the global pool.
+    pub fn pool(mut self, pool: &BufPool) -> Self {
+        self.pool = PoolRef::Owned(pool.clone());
+        self
+    }
+
+    /// Get back the reader. A frame read partway is lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for PooledFrames<R> {
+    type Item = Result<Bytes, std::io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.done {
+            return Poll::Ready(None);
+        }
+        let res = std::task::ready!(this.state.poll_frame(
+            cx,
+            Pin::new(&mut this.reader),
+            &this.config,
+            &this.pool
+        ));
+        this.done = !matches!(res, Ok(Some(_)));
+        Poll::Ready(res.transpose())
+    }
+}
+
+/// Progress through one frame.
+#[derive(Default)]
+struct FrameState {
+    header: [u8; 8],
+    header_filled: usize,
+    body: Option<FrameBody>,
+}
+
+enum FrameBody {
+    /// A pooled buffer for a frame of the given length, filled this far.
+    Pooled(BufGuard, usize, usize),
+    /// A frame too big for any size class, and how many bytes of it are still to come.
+    Large(BytesMut, usize),
+}
+
+impl FrameState {
+    fn poll_frame<R: AsyncRead + ?Sized>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut rdr: Pin<&mut R>,
+        config: &FrameConfig,
+        pool: &PoolRef,
+    ) -> Poll<Result<Option<Bytes>, std::io::Error>> {
+        let width = config.header_width.bytes();
+        while self.header_filled < width {
+            let n = std::task::ready!(retry_interrupted(|| rdr
+                .as_mut()
+                .poll_read(cx, &mut self.header[self.header_filled..width])))?;
+            if n == 0 {
+                if self.header_filled == 0 {
+                    return Poll::Ready(Ok(None));
+                }
+                return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+            self.header_filled += n;
+        }
+        loop {
+            match &mut self.body {
+                None => {
+                    let len = config.decode(&self.header[..width])?;
+                    let largest = pool.get().classes().last().unwrap_or(0);
+                    self.body = Some(if len != 0 && len <= largest {
+                        let (buf, _) = std::task::ready!(pool.poll_acquire(cx, len));
+                        FrameBody::Pooled(buf, len, 0)
+                    } else {
+                        FrameBody::Large(BytesMut::new(), len)
+                    });
+                }
+                Some(FrameBody::Pooled(buf, len, filled)) => {
+                    if *filled == *len {
+                        break;
+                    }
+                    let dst = &mut init_buf(buf, *len)[*filled..];
+                    let n =
+                        std::task::ready!(retry_interrupted(|| rdr.as_mut().poll_read(cx, dst)))?;
+                    if n == 0 {
+                        return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    *filled += n;
+                }
+                Some(FrameBody::Large(acc, remaining)) => {
+                    if *remaining == 0 {
+                        break;
+                    }
+                    let want = *remaining;
+                    let n = std::task::ready!(poll_pooled_read(rdr.as_mut(), cx, want, |chunk| {
+                        acc.reserve(want);
+                        acc.extend_from_slice(chunk);
+                        chunk.len()
+                    }))?;
+                    if n == 0 {
+                        return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    *remaining -= n;
+                }
+            }
+        }
+        let frame = match std::mem::take(self).body {
+            Some(FrameBody::Pooled(buf, len, _)) => PooledBytes::new(buf, len).into(),
+            Some(FrameBody::Large(acc, _)) => acc.freeze(),
+            None => unreachable!("frames are only finished once their body is"),
+        };
+        Poll::Ready(Ok(Some(frame)))
+    }
+}
+
+/// A `Sink<Bytes>` writing each item as a length-prefixed frame, batched into pooled buffers like
+/// [`crate::PooledSink`] does.
+pub struct PooledFrameSink<W> {
+    writer: PooledBufWriter<W>,
+    config: FrameConfig,
+    /// The header of the frame being written, and how much of it is still to go.
+    header: [u8; 8],
+    header_pos: usize,
+    header_len: usize,
+    pending: Bytes,
+}
+
+impl<W: AsyncWrite + Unpin> PooledFrameSink<W> {
+    /// Wrap a writer, framing every item as `config` says.
+    pub fn new(writer: W, config: FrameConfig) -> Self {
+        Self {
+            writer: PooledBufWriter::new(writer),
+            config,
+            header: [0; 8],
+            header_pos: 0,
+            header_len: 0,
+            pending: Bytes::new(),
+        }
+    }
+
+    /// Unwrap the writer, discarding anything queued but not yet flushed.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+
+    fn poll_drain_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        while self.header_pos < self.header_len {
+            let rest = &self.header[self.header_pos..self.header_len];
+            let n = std::task::ready!(Pin::new(&mut self.writer).poll_write(cx, rest))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.header_pos += n;
+        }
+        while !self.pending.is_empty() {
+            let n = std::task::ready!(Pin::new(&mut self.writer).poll_write(cx, &self.pending))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.pending.advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Sink<Bytes> for PooledFrameSink<W> {
+    type Error = std::io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_drain_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        debug_assert!(this.pending.is_empty(), "start_send without poll_ready");
+        this.header = this.config.encode(item.len())?;
+        this.header_pos = 0;
+        this.header_len = this.config.header_width.bytes();
+        this.pending = item;
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_drain_pending(cx))?;
+        Pin::new(&mut this.writer).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_drain_pending(cx))?;
+        Pin::new(&mut this.writer).poll_close(cx)
+    }
+}
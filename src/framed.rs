@@ -0,0 +1,101 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::BytesMut;
+use futures_util::{AsyncRead, Stream};
+use tokio_util::codec::Decoder;
+
+use crate::{chunk_size, poll_pooled_read};
+
+/// Decode frames from `reader` with any `tokio_util` [`Decoder`], reading through pooled buffers.
+///
+/// `FramedRead` keeps a buffer of its own per reader, reserved up front and grown to the largest
+/// frame it ever saw. Here reads go into pooled buffers that are returned right away, and only the
+/// bytes of a frame still being decoded are kept in between, so an idle stream holds no buffer
+/// at all. At EOF, whatever is left goes to [`Decoder::decode_eof`]. The stream ends after the
+/// first error.
+pub fn pooled_framed<R: AsyncRead + Unpin, D: Decoder>(
+    reader: R,
+    decoder: D,
+) -> PooledFramed<R, D> {
+    PooledFramed {
+        reader,
+        decoder,
+        buf: BytesMut::new(),
+        eof: false,
+        done: false,
+    }
+}
+
+/// The stream returned by [`pooled_framed`].
+pub struct PooledFramed<R, D> {
+    reader: R,
+    decoder: D,
+    /// The start of a frame that hasn't fully arrived yet.
+    buf: BytesMut,
+    eof: bool,
+    done: bool,
+}
+
+impl<R, D> PooledFramed<R, D> {
+    /// The decoder, e.g. to change its settings between frames.
+    pub fn decoder_mut(&mut self) -> &mut D {
+        &mut self.decoder
+    }
+
+    /// Get back the reader and decoder, along with any bytes read but not decoded yet.
+    pub fn into_parts(self) -> (R, D, BytesMut) {
+        (self.reader, self.decoder, self.buf)
+    }
+}
+
+impl<R: AsyncRead + Unpin, D: Decoder + Unpin> Stream for PooledFramed<R, D> {
+    type Item = Result<D::Item, D::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if this.done {
+                return Poll::Ready(None);
+            }
+            let res = if this.eof {
+                this.decoder.decode_eof(&mut this.buf)
+            } else if this.buf.is_empty() {
+                Ok(None)
+            } else {
+                this.decoder.decode(&mut this.buf)
+            };
+            match res {
+                Ok(Some(frame)) => {
+                    if this.buf.is_empty() && this.buf.capacity() > chunk_size() {
+                        // don't keep the memory of an unusually big frame around
+                        this.buf = BytesMut::new();
+                    }
+                    return Poll::Ready(Some(Ok(frame)));
+                }
+                Ok(None) if this.eof => {
+                    this.done = true;
+                    return Poll::Ready(None);
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
+            }
+            let buf = &mut this.buf;
+            let read = poll_pooled_read(Pin::new(&mut this.reader), cx, chunk_size(), |data| {
+                buf.extend_from_slice(data);
+                data.len()
+            });
+            match std::task::ready!(read) {
+                Ok(0) => this.eof = true,
+                Ok(_) => {}
+                Err(err) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(err.into())));
+                }
+            }
+        }
+    }
+}
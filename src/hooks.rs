@@ -0,0 +1,22 @@
+/// Callbacks a [`crate::BufPool`] makes as buffers move in and out of it, for plugging in
+/// accounting, leak tracking or tracing; see [`crate::BufPoolBuilder::hooks`].
+///
+/// Every method does nothing by default and is passed the buffer's size. They run on the thread
+/// doing the pool operation, on the hot path of every pooled read and copy, so they should be
+/// cheap and must not block.
+pub trait PoolHooks: Send + Sync + 'static {
+    /// A buffer was freshly allocated, because there was no idle one to reuse.
+    fn on_alloc(&self, size: usize) {
+        let _ = size;
+    }
+
+    /// An idle buffer was handed out again.
+    fn on_reuse(&self, size: usize) {
+        let _ = size;
+    }
+
+    /// A buffer came back to the pool, to be cached or freed.
+    fn on_release(&self, size: usize) {
+        let _ = size;
+    }
+}
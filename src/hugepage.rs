@@ -0,0 +1,101 @@
+use std::alloc::Layout;
+use std::ptr::NonNull;
+
+use crate::BufferSource;
+
+/// The size of the hugepages [`HugePageSource`] maps buffers onto.
+pub const HUGE_PAGE_SIZE: usize = 2 << 20;
+
+/// A [`BufferSource`] that backs large buffers with 2 MiB hugepages, cutting the TLB misses a
+/// high-throughput relay takes streaming through many megabytes of buffers.
+///
+/// Buffers of at least [`HugePageSource::min_size`] bytes are mapped with `MAP_HUGETLB`. When no
+/// hugepages are reserved, which is the default on most systems, they are mapped normally and
+/// offered to transparent hugepages with `madvise` instead. Smaller buffers, and any the kernel
+/// won't map at all, come from the global allocator as usual. Each mapping is rounded up to a
+/// whole hugepage, so size classes are best set to multiples of [`HUGE_PAGE_SIZE`].
+///
+/// ```no_run
+/// use async_io_bufpool::{BufPool, BufPoolConfig, HugePageSource, HUGE_PAGE_SIZE};
+///
+/// let pool = BufPool::with_source(
+///     BufPoolConfig {
+///         size_classes: vec![HUGE_PAGE_SIZE],
+///         ..Default::default()
+///     },
+///     HugePageSource::default(),
+/// );
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct HugePageSource {
+    min_size: usize,
+}
+
+impl HugePageSource {
+    /// Map buffers of `min_size` bytes and up onto hugepages.
+    pub fn new(min_size: usize) -> Self {
+        Self { min_size }
+    }
+
+    /// The smallest buffer that goes on hugepages.
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+}
+
+impl Default for HugePageSource {
+    /// Use hugepages for buffers of a whole hugepage or more, where none of the page is wasted.
+    fn default() -> Self {
+        Self::new(HUGE_PAGE_SIZE)
+    }
+}
+
+/// Map `len` bytes of anonymous memory with the extra `flags`.
+fn map(len: usize, flags: libc::c_int) -> Option<NonNull<u8>> {
+    // SAFETY: a fresh private anonymous mapping doesn't touch any existing memory
+    let ptr = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | flags,
+            -1,
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        return None;
+    }
+    NonNull::new(ptr.cast())
+}
+
+// SAFETY: mappings are at least `layout.size()` long, checked to be aligned, and only unmapped in
+// `recycle`
+unsafe impl BufferSource for HugePageSource {
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        if layout.size() < self.min_size || layout.align() > HUGE_PAGE_SIZE {
+            return None;
+        }
+        let len = layout.size().next_multiple_of(HUGE_PAGE_SIZE);
+        if let Some(ptr) = map(len, libc::MAP_HUGETLB) {
+            return Some(ptr);
+        }
+        let ptr = map(len, 0)?;
+        if !(ptr.as_ptr() as usize).is_multiple_of(layout.align()) {
+            // SAFETY: just mapped, and never handed out
+            unsafe { libc::munmap(ptr.as_ptr().cast(), len) };
+            return None;
+        }
+        // only advice, so whether the kernel takes it doesn't matter
+        // SAFETY: the range is exactly the mapping just made
+        unsafe { libc::madvise(ptr.as_ptr().cast(), len, libc::MADV_HUGEPAGE) };
+        Some(ptr)
+    }
+
+    unsafe fn recycle(&self, ptr: NonNull<u8>, layout: Layout) {
+        libc::munmap(
+            ptr.as_ptr().cast(),
+            layout.size().next_multiple_of(HUGE_PAGE_SIZE),
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use std::future::Future;
+
+use crate::{BufGuard, BufPool, PooledBytes};
+
+/// A buffer leased from a [`crate::BufPool`], returned to it when dropped.
+///
+/// Unlike the buffers the `pooled_*` functions use internally, a lease can be held across
+/// `.await`s, which makes it the building block for pooled protocols of your own. It derefs to
+/// the whole buffer, already initialized, and counts against the pool's outstanding-bytes budget
+/// for as long as it is held. Get one from [`crate::BufPool::acquire`].
+pub struct BufLease {
+    buf: BufGuard,
+    len: usize,
+}
+
+impl BufLease {
+    pub(crate) fn new(mut buf: BufGuard, len: usize) -> Self {
+        let len = buf.init(len).len();
+        Self { buf, len }
+    }
+
+    /// Keep the first `len` bytes (capped at the lease's length) as read-only [`PooledBytes`],
+    /// still backed by the same pooled buffer.
+    pub fn freeze(self, len: usize) -> PooledBytes {
+        PooledBytes::new(self.buf, len.min(self.len))
+    }
+}
+
+impl std::ops::Deref for BufLease {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl std::ops::DerefMut for BufLease {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[..self.len]
+    }
+}
+
+impl AsRef<[u8]> for BufLease {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsMut<[u8]> for BufLease {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+impl std::fmt::Debug for BufLease {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufLease").field("len", &self.len).finish()
+    }
+}
+
+/// Read once through an operation that takes ownership of the buffer until it completes, the way
+/// completion-based runtimes (monoio, compio, tokio-uring) do their I/O.
+///
+/// `read` gets a lease from the global pool and hands it back with the result, as in
+/// `pooled_read_owned(|buf| file.read(buf))` once the lease is wrapped in whatever buffer trait
+/// the runtime wants. If this future is dropped midway, the lease stays with the runtime until
+/// the kernel is done with it and only then goes back to the pool. Interrupted reads are retried
+/// with the same buffer.
+pub async fn pooled_read_owned<F, Fut>(read: F) -> Result<PooledBytes, std::io::Error>
+where
+    F: FnMut(BufLease) -> Fut,
+    Fut: Future<Output = (Result<usize, std::io::Error>, BufLease)>,
+{
+    BufPool::global().read_owned(read).await
+}
@@ -1,17 +1,116 @@
 use std::future::Future;
 
 use bytes::Bytes;
-use crossbeam_queue::SegQueue;
 use futures_util::AsyncRead;
 
-static POOL: SegQueue<Vec<u8>> = SegQueue::new();
+use crate::buf::PoolBuf;
+
+/// Emit a `tracing` event with the `tracing` feature, and nothing at all without it, so the
+/// arguments mustn't have side effects.
+macro_rules! trace_event {
+    ($level:ident, $($args:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($args)+);
+    }};
+}
+
+mod adapters;
+#[cfg(feature = "http-body")]
+mod body;
+mod broadcast;
+mod buf;
+mod copy;
+#[cfg(all(feature = "copy-file-range", target_os = "linux"))]
+mod copy_file;
+mod error;
+mod frame;
+#[cfg(feature = "codec")]
+mod framed;
+mod hooks;
+#[cfg(all(feature = "hugepages", target_os = "linux"))]
+mod hugepage;
+mod lease;
+mod metrics;
+#[cfg(all(feature = "numa", target_os = "linux"))]
+mod numa;
+mod pool;
+mod pooled_bytes;
+#[cfg(all(feature = "memory-pressure", target_os = "linux"))]
+mod pressure;
+mod rate;
+mod read;
+mod retry;
+#[cfg(all(feature = "sendfile", any(target_os = "linux", target_os = "macos")))]
+mod sendfile;
+mod source;
+#[cfg(all(feature = "splice", target_os = "linux"))]
+mod splice;
+mod stream;
+mod sync;
+#[cfg(any(
+    all(feature = "splice", target_os = "linux"),
+    all(feature = "sendfile", any(target_os = "linux", target_os = "macos")),
+    all(feature = "copy-file-range", target_os = "linux")
+))]
+mod sys;
+mod time;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+mod write;
+pub use adapters::*;
+#[cfg(feature = "http-body")]
+pub use body::*;
+pub use broadcast::*;
+pub use copy::*;
+#[cfg(all(feature = "copy-file-range", target_os = "linux"))]
+pub use copy_file::*;
+pub use error::*;
+pub use frame::*;
+#[cfg(feature = "codec")]
+pub use framed::*;
+pub use hooks::*;
+#[cfg(all(feature = "hugepages", target_os = "linux"))]
+pub use hugepage::*;
+pub use lease::*;
+pub use pool::*;
+pub use pooled_bytes::*;
+#[cfg(all(feature = "memory-pressure", target_os = "linux"))]
+pub use pressure::*;
+pub use rate::*;
+pub use read::*;
+pub use retry::*;
+#[cfg(all(feature = "sendfile", any(target_os = "linux", target_os = "macos")))]
+pub use sendfile::*;
+pub use source::*;
+#[cfg(all(feature = "splice", target_os = "linux"))]
+pub use splice::*;
+pub use stream::*;
+pub use sync::*;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub use uring::*;
+pub use write::*;
+
+const BUF_SIZE: usize = 8192;
 
 /// Read an async reader into a buffer, while not consuming any memory before the read unblocks.
+///
+/// The returned `Bytes` holds on to the pooled buffer it was read into, which goes back to the
+/// pool once the last clone is dropped.
+///
+/// `Interrupted` errors are retried internally; see [`pooled_read_raw`] to see them instead.
 pub async fn pooled_read(rdr: impl AsyncRead + Unpin) -> Result<Bytes, std::io::Error> {
-    PooledOnceReader(rdr).await
+    PooledOnceReader(rdr, true).await
+}
+
+/// Like [`pooled_read`], but surfaces `Interrupted` errors from the reader as-is.
+pub async fn pooled_read_raw(rdr: impl AsyncRead + Unpin) -> Result<Bytes, std::io::Error> {
+    PooledOnceReader(rdr, false).await
 }
 
-struct PooledOnceReader<T: AsyncRead>(T);
+/// The second field controls whether `Interrupted` errors get retried.
+struct PooledOnceReader<T: AsyncRead>(T, bool);
 
 impl<T: AsyncRead> Future for PooledOnceReader<T> {
     type Output = Result<Bytes, std::io::Error>;
@@ -20,21 +119,127 @@ impl<T: AsyncRead> Future for PooledOnceReader<T> {
         self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Self::Output> {
-        let mut free_buf = POOL.pop().unwrap_or_else(|| vec![0u8; 8192]);
-        let pinned = unsafe { std::pin::Pin::map_unchecked_mut(self, |s| &mut s.0) };
-        match pinned.poll_read(cx, &mut free_buf) {
-            std::task::Poll::Ready(Ok(n)) => {
-                free_buf.truncate(n);
-                std::task::Poll::Ready(Ok(free_buf.into()))
-            }
-            std::task::Poll::Ready(Err(err)) => {
-                POOL.push(free_buf);
-                std::task::Poll::Ready(Err(err))
-            }
-            std::task::Poll::Pending => {
-                POOL.push(free_buf);
-                std::task::Poll::Pending
-            }
+        let (mut free_buf, _) =
+            std::task::ready!(pool::PoolRef::Global.poll_acquire(cx, chunk_size()));
+        let retry = self.1;
+        let mut pinned = unsafe { std::pin::Pin::map_unchecked_mut(self, |s| &mut s.0) };
+        let buf = init_buf(&mut free_buf, chunk_size());
+        let res = if retry {
+            retry_interrupted(|| pinned.as_mut().poll_read(cx, buf))
+        } else {
+            pinned.poll_read(cx, buf)
+        };
+        res.map_ok(|n| PooledBytes::new(free_buf, n).into())
+    }
+}
+
+/// How much a read without an explicit size asks for: the global pool's buffer size, which is
+/// 8 KiB unless configured otherwise.
+pub(crate) fn chunk_size() -> usize {
+    BufPool::global().buf_size()
+}
+
+/// Take a buffer from the global pool, allocating a fresh one if it is empty.
+pub(crate) fn take_buf() -> PoolBuf {
+    take_buf_tracked().0
+}
+
+/// Like [`take_buf`], but also reports whether the buffer was reused from the pool.
+pub(crate) fn take_buf_tracked() -> (PoolBuf, bool) {
+    let pool = BufPool::global();
+    pool.take_tracked(pool.buf_size())
+}
+
+/// Get the first `len` bytes of a pooled buffer (capped at its capacity), initializing them if
+/// no earlier read has.
+///
+/// `AsyncRead` only accepts initialized slices, so this is where the zeroing cost is paid, once
+/// per buffer and only for the prefix that gets used.
+pub(crate) fn init_buf(buf: &mut PoolBuf, len: usize) -> &mut [u8] {
+    buf.init(len)
+}
+
+/// Take a buffer from the smallest size class that fits a read of `len` bytes.
+pub(crate) fn take_buf_for(len: usize) -> PoolBuf {
+    take_buf_for_tracked(len).0
+}
+
+/// Like [`take_buf_for`], but also reports whether the buffer was reused from the pool.
+pub(crate) fn take_buf_for_tracked(len: usize) -> (PoolBuf, bool) {
+    BufPool::global().take_tracked(len)
+}
+
+/// Return a buffer obtained from [`take_buf`] or [`take_buf_for`] to the global pool.
+pub(crate) fn give_buf(buf: PoolBuf) {
+    BufPool::global().give(buf);
+}
+
+/// A pooled buffer that goes back to the pool when dropped.
+///
+/// Holding buffers through this guard recycles them on every exit path, including errors and the
+/// owning future being dropped halfway through a write. The last field is how many bytes the
+/// buffer holds of the pool's outstanding-bytes budget.
+pub(crate) struct BufGuard(PoolBuf, pool::PoolRef, usize);
+
+impl BufGuard {
+    /// Guard a buffer taken from `pool` that holds `reserved` bytes of its budget, or none for
+    /// buffers taken outside it.
+    pub(crate) fn new(buf: PoolBuf, pool: pool::PoolRef, reserved: usize) -> Self {
+        Self(buf, pool, reserved)
+    }
+}
+
+impl std::ops::Deref for BufGuard {
+    type Target = PoolBuf;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for BufGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Drop for BufGuard {
+    fn drop(&mut self) {
+        let pool = self.1.get();
+        pool.give(std::mem::take(&mut self.0));
+        pool.release(self.2);
+    }
+}
+
+/// Poll a single read of at most `limit` bytes into a pooled buffer, handing the filled part to `f`.
+///
+/// The buffer goes back to the pool on every path, so nothing is held between polls. Limits past
+/// [`chunk_size`] use a buffer from the matching size class, up to the largest one.
+pub(crate) fn poll_pooled_read<R: AsyncRead + ?Sized, T>(
+    mut rdr: std::pin::Pin<&mut R>,
+    cx: &mut std::task::Context<'_>,
+    limit: usize,
+    f: impl FnOnce(&[u8]) -> T,
+) -> std::task::Poll<Result<T, std::io::Error>> {
+    let mut free_buf = take_buf_for(limit);
+    let buf = init_buf(&mut free_buf, limit);
+    let res = match retry_interrupted(|| rdr.as_mut().poll_read(cx, buf)) {
+        std::task::Poll::Ready(Ok(n)) => std::task::Poll::Ready(Ok(f(&free_buf[..n]))),
+        std::task::Poll::Ready(Err(err)) => std::task::Poll::Ready(Err(err)),
+        std::task::Poll::Pending => std::task::Poll::Pending,
+    };
+    give_buf(free_buf);
+    res
+}
+
+/// Repeat a poll for as long as it fails with `Interrupted`, like `std::io::Read::read_exact` does.
+pub(crate) fn retry_interrupted<T>(
+    mut poll: impl FnMut() -> std::task::Poll<Result<T, std::io::Error>>,
+) -> std::task::Poll<Result<T, std::io::Error>> {
+    loop {
+        match poll() {
+            std::task::Poll::Ready(Err(err)) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            res => return res,
         }
     }
 }
@@ -0,0 +1,112 @@
+//! Pool health reported to the `metrics` facade with the `metrics` feature, and nothing at all
+//! without it. Totals are summed over every pool in the process.
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+
+#[cfg(feature = "metrics")]
+use ::metrics::{counter, describe_counter, describe_gauge, gauge, Unit};
+
+/// Describe the metrics to the recorder, once it's likely to be installed.
+#[cfg(feature = "metrics")]
+fn describe() {
+    static DESCRIBED: std::sync::Once = std::sync::Once::new();
+    DESCRIBED.call_once(|| {
+        describe_counter!(
+            "bufpool_buffers_reused_total",
+            "Buffers handed out again from a pool's cache."
+        );
+        describe_counter!(
+            "bufpool_buffer_misses_total",
+            "Buffers freshly allocated because no idle one could be reused."
+        );
+        describe_counter!(
+            "bufpool_buffers_discarded_total",
+            "Returned buffers freed instead of cached, because the pool was at its caps."
+        );
+        describe_counter!(
+            "bufpool_buffers_evicted_total",
+            "Idle buffers freed by the idle timeout or shrinking."
+        );
+        describe_gauge!(
+            "bufpool_cached_bytes",
+            Unit::Bytes,
+            "Bytes held in idle buffers."
+        );
+        describe_gauge!(
+            "bufpool_buffers_in_use",
+            "Buffers taken from a pool and not returned yet."
+        );
+        describe_counter!(
+            "bufpool_copied_bytes_total",
+            Unit::Bytes,
+            "Bytes written by pooled copies."
+        );
+        describe_gauge!("bufpool_active_copies", "Pooled copies in progress.");
+    });
+}
+
+/// A buffer of `size` bytes left a pool, from its cache if `reused`.
+pub(crate) fn buffer_taken(size: usize, reused: bool) {
+    #[cfg(feature = "metrics")]
+    {
+        describe();
+        if reused {
+            counter!("bufpool_buffers_reused_total").increment(1);
+            gauge!("bufpool_cached_bytes").decrement(size as f64);
+        } else {
+            counter!("bufpool_buffer_misses_total").increment(1);
+        }
+        gauge!("bufpool_buffers_in_use").increment(1.0);
+    }
+}
+
+/// A buffer came back to a pool.
+pub(crate) fn buffer_returned() {
+    #[cfg(feature = "metrics")]
+    gauge!("bufpool_buffers_in_use").decrement(1.0);
+}
+
+/// A returned buffer of `size` bytes was cached, or freed because the pool was at its caps.
+pub(crate) fn buffer_kept(size: usize, cached: bool) {
+    #[cfg(feature = "metrics")]
+    if cached {
+        gauge!("bufpool_cached_bytes").increment(size as f64);
+    } else {
+        counter!("bufpool_buffers_discarded_total").increment(1);
+    }
+}
+
+/// `count` idle buffers totalling `bytes` were freed.
+pub(crate) fn buffers_evicted(count: usize, bytes: usize) {
+    #[cfg(feature = "metrics")]
+    if count != 0 {
+        counter!("bufpool_buffers_evicted_total").increment(count as u64);
+        gauge!("bufpool_cached_bytes").decrement(bytes as f64);
+    }
+}
+
+/// A pooled copy wrote `bytes` more.
+pub(crate) fn copied(bytes: usize) {
+    #[cfg(feature = "metrics")]
+    counter!("bufpool_copied_bytes_total").increment(bytes as u64);
+}
+
+/// Counts a copy as active for as long as it is held.
+pub(crate) struct ActiveCopy(());
+
+impl ActiveCopy {
+    pub(crate) fn new() -> Self {
+        #[cfg(feature = "metrics")]
+        {
+            describe();
+            gauge!("bufpool_active_copies").increment(1.0);
+        }
+        Self(())
+    }
+}
+
+impl Drop for ActiveCopy {
+    fn drop(&mut self) {
+        #[cfg(feature = "metrics")]
+        gauge!("bufpool_active_copies").decrement(1.0);
+    }
+}
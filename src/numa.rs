@@ -0,0 +1,62 @@
+use std::sync::OnceLock;
+
+/// The NUMA node of each CPU, read from sysfs once. Empty if sysfs doesn't say.
+fn cpu_nodes() -> &'static [usize] {
+    static NODES: OnceLock<Vec<usize>> = OnceLock::new();
+    NODES.get_or_init(|| {
+        let mut nodes = Vec::new();
+        let Ok(dir) = std::fs::read_dir("/sys/devices/system/node") else {
+            return nodes;
+        };
+        for entry in dir.flatten() {
+            let Some(node) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("node"))
+                .and_then(|id| id.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            let Ok(cpus) = std::fs::read_to_string(entry.path().join("cpulist")) else {
+                continue;
+            };
+            for cpu in parse_cpulist(&cpus) {
+                if nodes.len() <= cpu {
+                    nodes.resize(cpu + 1, 0);
+                }
+                nodes[cpu] = node;
+            }
+        }
+        nodes
+    })
+}
+
+/// How many NUMA nodes there are, counting at least one.
+pub(crate) fn node_count() -> usize {
+    cpu_nodes().iter().max().map_or(1, |max| max + 1)
+}
+
+/// The node the calling thread is running on right now. Threads migrate, so this is only ever a
+/// hint about where memory it touches next is cheapest.
+pub(crate) fn current_node() -> usize {
+    // SAFETY: no arguments, and failure is reported as -1
+    let cpu = unsafe { libc::sched_getcpu() };
+    usize::try_from(cpu)
+        .ok()
+        .and_then(|cpu| cpu_nodes().get(cpu).copied())
+        .unwrap_or(0)
+}
+
+/// The CPUs in a sysfs list like `0-3,8-11`.
+fn parse_cpulist(list: &str) -> impl Iterator<Item = usize> + '_ {
+    list.trim()
+        .split(',')
+        .filter_map(|range| match range.split_once('-') {
+            Some((start, end)) => Some(start.parse().ok()?..=end.parse().ok()?),
+            None => {
+                let cpu = range.parse().ok()?;
+                Some(cpu..=cpu)
+            }
+        })
+        .flatten()
+}
@@ -0,0 +1,954 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use bytes::Bytes;
+use crossbeam_queue::SegQueue;
+use futures_util::{future::poll_fn, AsyncRead, AsyncWrite};
+
+use crate::time::Instant;
+use crate::{
+    buf::PoolBuf, metrics, BufGuard, BufLease, BufferSource, CopyError, PoolHooks, PooledBytes,
+    PooledCopy, BUF_SIZE,
+};
+
+/// The size classes a pool made with [`BufPool::new`] has on top of its own buffer size.
+pub const DEFAULT_SIZE_CLASSES: [usize; 5] = [4 << 10, 16 << 10, 64 << 10, 256 << 10, 1 << 20];
+
+/// How many idle buffers a pool caches unless configured otherwise, so a burst of thousands of
+/// concurrent copies doesn't leave all their buffers cached forever.
+pub const DEFAULT_MAX_CACHED_BUFFERS: usize = 1024;
+
+/// How many bytes a pool caches in idle buffers unless configured otherwise.
+#[cfg(not(target_arch = "wasm32"))]
+pub const DEFAULT_MAX_CACHED_BYTES: usize = 64 << 20;
+
+/// How many bytes a pool caches in idle buffers unless configured otherwise. A wasm module's
+/// memory can grow but never shrink, so whatever the pool caches at its peak stays claimed.
+#[cfg(target_arch = "wasm32")]
+pub const DEFAULT_MAX_CACHED_BYTES: usize = 4 << 20;
+
+/// How many idle buffers each thread keeps to itself unless configured otherwise, see
+/// [`BufPoolConfig::thread_cache`].
+pub const DEFAULT_THREAD_CACHE: usize = 4;
+
+static GLOBAL: OnceLock<BufPool> = OnceLock::new();
+
+/// Pools registered with [`BufPool::register`].
+static NAMED: RwLock<Vec<(String, BufPool)>> = RwLock::new(Vec::new());
+
+thread_local! {
+    /// Each thread's own few idle buffers of each pool's base class, see
+    /// [`BufPoolConfig::thread_cache`].
+    static LOCAL: RefCell<Vec<LocalCache>> = const { RefCell::new(Vec::new()) };
+}
+
+struct LocalCache {
+    pool: Weak<Inner>,
+    bufs: Vec<PoolBuf>,
+}
+
+/// Settings for a [`BufPool`], see [`BufPool::from_config`] and [`init_global`].
+///
+/// Start from `BufPoolConfig::default()` and override what needs tuning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufPoolConfig {
+    /// Size of the buffers used by reads and copies that don't ask for a particular size.
+    pub chunk_size: usize,
+    /// Further buffer sizes for reads that do; each read gets the smallest that fits.
+    pub size_classes: Vec<usize>,
+    /// Most idle buffers the pool keeps around, across all size classes.
+    pub max_cached_buffers: usize,
+    /// Most bytes the pool keeps around in idle buffers.
+    pub max_total_bytes: usize,
+    /// Free idle buffers the pool went this long without needing, so memory cached for a burst of
+    /// traffic goes back to the allocator once it's over. Checked lazily as buffers are returned,
+    /// so a buffer may linger for up to twice this long. `None` keeps idle buffers until
+    /// [`BufPool::shrink`] is called.
+    pub idle_timeout: Option<Duration>,
+    /// Most bytes that may be checked out of the pool at once in buffers held across polls: the
+    /// windows of in-progress copies and broadcasts, and buffers handed out by whole-buffer reads
+    /// as [`PooledBytes`] or `Bytes`. Once that much is out, those wait for buffers to come back
+    /// instead of allocating more, turning memory pressure into backpressure. Buffers borrowed
+    /// only for the duration of a single poll don't count. A budget smaller than one buffer still
+    /// lets one buffer out at a time.
+    pub max_outstanding_bytes: usize,
+    /// Alignment of the start of every buffer, in bytes; rounded up to a power of two. With 512
+    /// or 4096, pooled reads and copies can work on files opened with `O_DIRECT`: all buffer sizes
+    /// are rounded up to multiples of it too, so whole-buffer reads have valid lengths. Reads
+    /// limited to some other length, such as a copy's last chunk under a byte limit, still aren't.
+    pub alignment: usize,
+    /// Wipe buffers as they come back, before they are cached or freed, so secrets read through
+    /// the pool don't linger in memory that gets handed to other code later.
+    pub zero_on_return: bool,
+    /// How many idle buffers of [`BufPoolConfig::chunk_size`] each thread keeps to itself, out of
+    /// those the caps above allow. They are reused without going through the shared freelists, so
+    /// threads doing many reads and copies don't contend on those. They never time out, and only
+    /// the calling thread's are freed by [`BufPool::shrink`]. Zero sends every buffer through the
+    /// shared freelists.
+    ///
+    /// Each thread that uses the pool can end up holding `thread_cache * chunk_size` bytes this
+    /// way, so runtimes with hundreds of blocking threads may want it lower, and bulk workloads with
+    /// big chunks higher; for the global pool, set it through [`init_global`] before first use.
+    pub thread_cache: usize,
+}
+
+impl Default for BufPoolConfig {
+    /// 8 KiB chunks, the [`DEFAULT_SIZE_CLASSES`], byte alignment, and caching capped at
+    /// [`DEFAULT_MAX_CACHED_BUFFERS`] and [`DEFAULT_MAX_CACHED_BYTES`].
+    fn default() -> Self {
+        Self {
+            chunk_size: BUF_SIZE,
+            size_classes: DEFAULT_SIZE_CLASSES.to_vec(),
+            max_cached_buffers: DEFAULT_MAX_CACHED_BUFFERS,
+            max_total_bytes: DEFAULT_MAX_CACHED_BYTES,
+            idle_timeout: None,
+            max_outstanding_bytes: usize::MAX,
+            alignment: 1,
+            zero_on_return: false,
+            thread_cache: DEFAULT_THREAD_CACHE,
+        }
+    }
+}
+
+/// Replace the default global pool with one built from `config`, typically at startup.
+///
+/// Fails, handing the pool back, if the global pool has already been installed or used.
+pub fn init_global(config: BufPoolConfig) -> Result<(), BufPool> {
+    BufPool::from_config(config).install_global()
+}
+
+/// Fill the global pool with `n` buffers ahead of time, see [`BufPool::prewarm`].
+pub fn prewarm_global(n: usize) -> usize {
+    BufPool::global().prewarm(n)
+}
+
+/// Free the idle buffers of the global pool and every pool registered with
+/// [`BufPool::register`], returning how many bytes that released.
+///
+/// Meant to be called when the process is short on memory, e.g. from a handler for the
+/// orchestrator's memory-pressure notifications; the `memory-pressure` feature can also do it
+/// automatically on Linux, see `spawn_pressure_trimmer`. Pools used without registering them are
+/// left alone, and have to be shrunk with [`BufPool::shrink`] by whoever owns them. Buffers in
+/// other threads' caches (see [`BufPoolConfig::thread_cache`]) stay where they are.
+pub fn trim_under_pressure() -> usize {
+    let named: Vec<BufPool> = NAMED
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .iter()
+        .map(|(_, pool)| pool.clone())
+        .collect();
+    let freed = GLOBAL
+        .get()
+        .into_iter()
+        .chain(&named)
+        .map(BufPool::shrink)
+        .sum();
+    trace_event!(info, freed, "trimmed buffer pools under memory pressure");
+    freed
+}
+
+/// A pool of reusable read buffers.
+///
+/// The `pooled_*` functions all draw from the global pool, see [`BufPool::global`]; a separate
+/// pool keeps one subsystem's buffers (and their memory) apart from everybody else's. Cloning a
+/// `BufPool` gives another handle to the same pool.
+///
+/// With the `numa` feature on Linux, every size class keeps a freelist per NUMA node, and takes
+/// prefer buffers returned on the caller's node before falling back to the other nodes' ones.
+#[derive(Clone)]
+pub struct BufPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: BufPoolConfig,
+    /// Ascending buffer sizes.
+    classes: Box<[SizeClass]>,
+    /// Index of the class that reads without an explicit size use.
+    base: usize,
+    /// Idle buffers and bytes across all classes.
+    cached: AtomicUsize,
+    cached_bytes: AtomicUsize,
+    peak_cached_bytes: AtomicUsize,
+    allocated: AtomicU64,
+    reused: AtomicU64,
+    misses: AtomicU64,
+    discarded: AtomicU64,
+    evicted: AtomicU64,
+    /// When the pool was made, which idle-timeout deadlines are counted from.
+    created: Instant,
+    /// Nanoseconds after `created` when idle buffers are next checked for eviction.
+    next_sweep: AtomicU64,
+    /// Bytes reserved against `max_outstanding_bytes`.
+    outstanding: AtomicUsize,
+    in_use: AtomicUsize,
+    /// Tasks waiting for outstanding bytes to come back.
+    waiters: Mutex<Vec<Waker>>,
+    /// Where fresh buffers come from, if not the global allocator.
+    source: Option<Arc<dyn BufferSource>>,
+    hooks: Option<Box<dyn PoolHooks>>,
+    /// Addresses of the buffers handed out and not yet returned.
+    #[cfg(feature = "debug-pool")]
+    leases: Mutex<std::collections::HashSet<usize>>,
+}
+
+/// A snapshot of a pool's counters, from [`BufPool::stats`].
+///
+/// The counters are updated independently of each other, so a snapshot taken while the pool is
+/// busy may be slightly inconsistent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Buffers freshly allocated because no idle one could be reused.
+    pub allocated: u64,
+    /// Buffers handed out again from the cache.
+    pub reused: u64,
+    /// Requests that found no idle buffer of the size they needed.
+    pub misses: u64,
+    /// Returned buffers freed instead of cached, because the pool was at its caps.
+    pub discarded: u64,
+    /// Idle buffers freed by the idle timeout or [`BufPool::shrink`].
+    pub evicted: u64,
+    /// Idle buffers cached right now.
+    pub cached_buffers: usize,
+    /// The total size of those idle buffers.
+    pub cached_bytes: usize,
+    /// The most bytes ever cached at once.
+    pub peak_cached_bytes: usize,
+    /// Bytes currently checked out against [`BufPoolConfig::max_outstanding_bytes`].
+    pub outstanding_bytes: usize,
+    /// Buffers taken from the pool and not yet returned, whether or not they count against the
+    /// budget.
+    pub in_use: usize,
+}
+
+struct SizeClass {
+    size: usize,
+    /// One freelist per NUMA node with the `numa` feature, otherwise just one.
+    free: Box<[SegQueue<PoolBuf>]>,
+    /// Idle buffers across `free`.
+    idle: AtomicUsize,
+    /// The fewest idle buffers this class had since the last sweep: that many went unneeded the
+    /// whole time.
+    low: AtomicUsize,
+}
+
+impl SizeClass {
+    /// Take an idle buffer, preferring one from the current node's freelist.
+    fn pop(&self) -> Option<PoolBuf> {
+        let local = current_shard(self.free.len());
+        self.free[local].pop().or_else(|| {
+            self.free
+                .iter()
+                .enumerate()
+                .filter(|(shard, _)| *shard != local)
+                .find_map(|(_, free)| free.pop())
+        })
+    }
+
+    fn push(&self, buf: PoolBuf) {
+        self.free[current_shard(self.free.len())].push(buf);
+    }
+}
+
+#[cfg(all(feature = "numa", target_os = "linux"))]
+fn shard_count() -> usize {
+    crate::numa::node_count()
+}
+
+#[cfg(not(all(feature = "numa", target_os = "linux")))]
+fn shard_count() -> usize {
+    1
+}
+
+#[cfg(all(feature = "numa", target_os = "linux"))]
+fn current_shard(shards: usize) -> usize {
+    if shards == 1 {
+        return 0;
+    }
+    crate::numa::current_node() % shards
+}
+
+#[cfg(not(all(feature = "numa", target_os = "linux")))]
+fn current_shard(_shards: usize) -> usize {
+    0
+}
+
+impl BufPool {
+    /// A pool of `buf_size`-byte buffers that keeps at most `capacity` idle buffers.
+    ///
+    /// Reads with an explicit size get the smallest of `buf_size` and the
+    /// [`DEFAULT_SIZE_CLASSES`] that fits, so small reads don't tie up big buffers and big ones are
+    /// capped at 1 MiB (or `buf_size`, if that is bigger). Idle buffers beyond `capacity` are freed
+    /// instead of cached.
+    pub fn new(buf_size: usize, capacity: usize) -> Self {
+        Self::from_config(BufPoolConfig {
+            chunk_size: buf_size,
+            max_cached_buffers: capacity,
+            ..BufPoolConfig::default()
+        })
+    }
+
+    /// A pool with exactly the given size classes, keeping at most `capacity` idle buffers.
+    ///
+    /// Reads without an explicit size use the smallest class of at least 8 KiB, or the largest
+    /// class if none is that big.
+    pub fn with_classes(classes: &[usize], capacity: usize) -> Self {
+        let mut sizes: Vec<usize> = classes.iter().copied().filter(|size| *size > 0).collect();
+        if sizes.is_empty() {
+            sizes.push(BUF_SIZE);
+        }
+        sizes.sort_unstable();
+        let chunk_size = sizes
+            .iter()
+            .copied()
+            .find(|size| *size >= BUF_SIZE)
+            .unwrap_or(sizes[sizes.len() - 1]);
+        Self::from_config(BufPoolConfig {
+            chunk_size,
+            size_classes: sizes,
+            max_cached_buffers: capacity,
+            ..BufPoolConfig::default()
+        })
+    }
+
+    /// A pool set up as described by `config`.
+    pub fn from_config(config: BufPoolConfig) -> Self {
+        Self::build(config, None, None)
+    }
+
+    /// Like [`BufPool::from_config`], but with buffer memory coming from `source` instead of the
+    /// global allocator.
+    pub fn with_source(config: BufPoolConfig, source: impl BufferSource) -> Self {
+        Self::builder(config).source(source).build()
+    }
+
+    /// Start setting up a pool that needs more than a [`BufPoolConfig`], such as a
+    /// [`BufferSource`] or [`PoolHooks`].
+    pub fn builder(config: BufPoolConfig) -> BufPoolBuilder {
+        BufPoolBuilder {
+            config,
+            source: None,
+            hooks: None,
+        }
+    }
+
+    fn build(
+        mut config: BufPoolConfig,
+        source: Option<Arc<dyn BufferSource>>,
+        hooks: Option<Box<dyn PoolHooks>>,
+    ) -> Self {
+        config.alignment = config.alignment.max(1).next_power_of_two();
+        let align = config.alignment;
+        config.chunk_size = config.chunk_size.max(1).next_multiple_of(align);
+        let mut sizes: Vec<usize> = config
+            .size_classes
+            .iter()
+            .copied()
+            .chain([config.chunk_size])
+            .filter(|size| *size > 0)
+            .map(|size| size.next_multiple_of(align))
+            .collect();
+        sizes.sort_unstable();
+        sizes.dedup();
+        let base = sizes
+            .iter()
+            .position(|size| *size == config.chunk_size)
+            .unwrap_or(0);
+        let classes = sizes
+            .into_iter()
+            .map(|size| SizeClass {
+                size,
+                free: (0..shard_count()).map(|_| SegQueue::new()).collect(),
+                idle: AtomicUsize::new(0),
+                low: AtomicUsize::new(0),
+            })
+            .collect();
+        Self {
+            inner: Arc::new(Inner {
+                classes,
+                base,
+                cached: AtomicUsize::new(0),
+                cached_bytes: AtomicUsize::new(0),
+                peak_cached_bytes: AtomicUsize::new(0),
+                allocated: AtomicU64::new(0),
+                reused: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                discarded: AtomicU64::new(0),
+                evicted: AtomicU64::new(0),
+                created: Instant::now(),
+                next_sweep: AtomicU64::new(config.idle_timeout.map_or(u64::MAX, nanos)),
+                outstanding: AtomicUsize::new(0),
+                in_use: AtomicUsize::new(0),
+                waiters: Mutex::new(Vec::new()),
+                source,
+                hooks,
+                #[cfg(feature = "debug-pool")]
+                leases: Mutex::default(),
+                config,
+            }),
+        }
+    }
+
+    /// The process-wide pool used by the `pooled_*` functions.
+    ///
+    /// Unless another one was installed with [`BufPool::install_global`] or [`init_global`] first,
+    /// this is a pool with the default [`BufPoolConfig`].
+    pub fn global() -> &'static BufPool {
+        GLOBAL.get_or_init(|| BufPool::from_config(BufPoolConfig::default()))
+    }
+
+    /// Make `self` the global pool. Fails, handing the pool back, if the global pool has already
+    /// been installed or used.
+    pub fn install_global(self) -> Result<(), BufPool> {
+        GLOBAL.set(self)
+    }
+
+    /// Make `self` available process-wide as `name`, e.g. `"ingress"` or `"disk"`, so that
+    /// separate subsystems can share a pool with its own caps and budget without passing it around.
+    ///
+    /// Giving bulk transfers and latency-critical traffic pools of their own keeps one from
+    /// starving the other of buffers. Fails, handing the pool back, if `name` is already taken.
+    pub fn register(self, name: impl Into<String>) -> Result<(), BufPool> {
+        let name = name.into();
+        let mut named = NAMED.write().unwrap_or_else(|err| err.into_inner());
+        if named.iter().any(|(taken, _)| *taken == name) {
+            return Err(self);
+        }
+        named.push((name, self));
+        Ok(())
+    }
+
+    /// The pool registered as `name` with [`BufPool::register`], if any.
+    pub fn named(name: &str) -> Option<BufPool> {
+        NAMED
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .iter()
+            .find(|(taken, _)| taken == name)
+            .map(|(_, pool)| pool.clone())
+    }
+
+    /// How the pool was set up.
+    pub fn config(&self) -> &BufPoolConfig {
+        &self.inner.config
+    }
+
+    /// The size of the buffers used by reads that don't ask for a particular size.
+    pub fn buf_size(&self) -> usize {
+        self.inner.classes[self.inner.base].size
+    }
+
+    /// The pool's size classes, smallest first.
+    pub fn classes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.inner.classes.iter().map(|class| class.size)
+    }
+
+    /// How many idle buffers the pool may cache.
+    pub fn capacity(&self) -> usize {
+        self.inner.config.max_cached_buffers
+    }
+
+    /// A snapshot of the pool's counters.
+    pub fn stats(&self) -> PoolStats {
+        let inner = &self.inner;
+        PoolStats {
+            allocated: inner.allocated.load(Ordering::Relaxed),
+            reused: inner.reused.load(Ordering::Relaxed),
+            misses: inner.misses.load(Ordering::Relaxed),
+            discarded: inner.discarded.load(Ordering::Relaxed),
+            evicted: inner.evicted.load(Ordering::Relaxed),
+            cached_buffers: inner.cached.load(Ordering::Relaxed),
+            cached_bytes: inner.cached_bytes.load(Ordering::Relaxed),
+            peak_cached_bytes: inner.peak_cached_bytes.load(Ordering::Relaxed),
+            outstanding_bytes: inner.outstanding.load(Ordering::Relaxed),
+            in_use: inner.in_use.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Run `f` with a fresh pool of its own, then check that everything it took from the pool
+    /// came back, as with [`BufPool::assert_all_returned`].
+    ///
+    /// Meant for tests: the `pooled_*` functions share the global pool with whatever else runs at
+    /// the same time, whereas the pool-taking variants ([`BufPool::read`],
+    /// [`crate::PooledCopy::pool`], ...) used on a scoped pool see only the test's own traffic.
+    pub fn scoped<T>(f: impl FnOnce(&BufPool) -> T) -> T {
+        let pool = BufPool::from_config(BufPoolConfig::default());
+        let out = f(&pool);
+        pool.assert_all_returned();
+        out
+    }
+
+    /// Panic unless every buffer taken from the pool has been returned to it, e.g. to catch a leaked
+    /// [`PooledBytes`] or lease at the end of a test.
+    #[track_caller]
+    pub fn assert_all_returned(&self) {
+        let stats = self.stats();
+        assert!(
+            stats.in_use == 0 && stats.outstanding_bytes == 0,
+            "{} pooled buffers still in use ({} bytes against the budget)",
+            stats.in_use,
+            stats.outstanding_bytes,
+        );
+    }
+
+    /// Allocate `n` buffers of [`BufPool::buf_size`] and cache them, so the first burst of reads
+    /// after startup finds them ready instead of paying for allocation.
+    ///
+    /// The buffers are zeroed up front too, which also faults their pages in. Returns how many
+    /// were cached, which is fewer than `n` if that would go past the pool's caps. An idle timeout
+    /// frees prewarmed buffers like any others once they go unused that long.
+    pub fn prewarm(&self, n: usize) -> usize {
+        let inner = &self.inner;
+        let size = self.buf_size();
+        for prewarmed in 0..n {
+            let mut buf = self.lend(self.allocate(size));
+            buf.init(size);
+            inner.allocated.fetch_add(1, Ordering::Relaxed);
+            inner.in_use.fetch_add(1, Ordering::Relaxed);
+            metrics::buffer_taken(size, false);
+            if !self.give(buf) {
+                return prewarmed;
+            }
+        }
+        n
+    }
+
+    /// Free every idle buffer the pool holds, returning how many bytes that released.
+    ///
+    /// Buffers currently in use are unaffected and still come back to the pool afterwards. Of the
+    /// per-thread caches, only the calling thread's is emptied.
+    pub fn shrink(&self) -> usize {
+        let local = self.with_local(std::mem::take).unwrap_or_default();
+        let mut freed = local.iter().map(|buf| buf.capacity()).sum();
+        let inner = &self.inner;
+        inner.cached.fetch_sub(local.len(), Ordering::Relaxed);
+        inner.cached_bytes.fetch_sub(freed, Ordering::Relaxed);
+        inner
+            .evicted
+            .fetch_add(local.len() as u64, Ordering::Relaxed);
+        metrics::buffers_evicted(local.len(), freed);
+        for class in self.inner.classes.iter() {
+            while self.evict_one(class) {
+                freed += class.size;
+            }
+        }
+        trace_event!(debug, freed, "pool shrunk");
+        freed
+    }
+
+    /// Lease a buffer of [`BufPool::buf_size`] bytes, waiting if the outstanding-bytes budget is
+    /// used up.
+    pub async fn acquire(&self) -> BufLease {
+        self.acquire_len(self.buf_size()).await
+    }
+
+    /// Lease a buffer of `len` bytes from the smallest size class that fits, waiting if the
+    /// outstanding-bytes budget is used up. Past the largest class, the lease is only as long as
+    /// that class's buffers.
+    pub async fn acquire_len(&self, len: usize) -> BufLease {
+        let pool = PoolRef::Owned(self.clone());
+        let (buf, _) = poll_fn(|cx| pool.poll_acquire(cx, len)).await;
+        BufLease::new(buf, len)
+    }
+
+    /// Read once from `rdr` into one of this pool's buffers, as [`crate::pooled_read`] does with
+    /// the global pool.
+    pub async fn read(&self, rdr: impl AsyncRead + Unpin) -> Result<Bytes, std::io::Error> {
+        self.read_recycled(rdr).await.map(Bytes::from)
+    }
+
+    /// Like [`BufPool::read`], but hands back the pooled buffer itself, as
+    /// [`crate::pooled_read_recycled`] does.
+    pub async fn read_recycled(
+        &self,
+        rdr: impl AsyncRead + Unpin,
+    ) -> Result<PooledBytes, std::io::Error> {
+        PooledBytes::read_from(PoolRef::Owned(self.clone()), rdr).await
+    }
+
+    /// Read once through an operation that owns one of this pool's buffers while it runs, as
+    /// [`crate::pooled_read_owned`] does with the global pool.
+    pub async fn read_owned<F, Fut>(&self, mut read: F) -> Result<PooledBytes, std::io::Error>
+    where
+        F: FnMut(BufLease) -> Fut,
+        Fut: Future<Output = (Result<usize, std::io::Error>, BufLease)>,
+    {
+        let mut lease = self.acquire().await;
+        loop {
+            let res;
+            (res, lease) = read(lease).await;
+            match res {
+                Ok(n) => return Ok(lease.freeze(n)),
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Copy everything from `reader` into `writer` through this pool's buffers, as
+    /// [`crate::pooled_copy`] does through the global pool's.
+    pub async fn copy(
+        &self,
+        reader: impl AsyncRead + Unpin,
+        writer: impl AsyncWrite + Unpin,
+    ) -> Result<u64, CopyError> {
+        PooledCopy::new(reader, writer).pool(self).await
+    }
+
+    /// Take a buffer from the smallest class that fits a read of `len` bytes, capped at the largest
+    /// class, and report whether it was reused rather than freshly allocated.
+    ///
+    /// Fresh buffers are not zeroed up front: the buffer's length tracks how much of the capacity
+    /// has been initialized so far, and [`init_buf`] extends it only as far as a read actually
+    /// needs.
+    pub(crate) fn take_tracked(&self, len: usize) -> (PoolBuf, bool) {
+        let (buf, reused) = self.take_idle_or_new(len);
+        metrics::buffer_taken(buf.capacity(), reused);
+        trace_event!(trace, size = buf.capacity(), reused, "pooled buffer taken");
+        (self.lend(buf), reused)
+    }
+
+    fn take_idle_or_new(&self, len: usize) -> (PoolBuf, bool) {
+        let class = self.class_for(len);
+        let inner = &self.inner;
+        inner.in_use.fetch_add(1, Ordering::Relaxed);
+        if self.is_local(class) {
+            if let Some(buf) = self.with_local(|bufs| bufs.pop()).flatten() {
+                inner.cached.fetch_sub(1, Ordering::Relaxed);
+                inner.cached_bytes.fetch_sub(class.size, Ordering::Relaxed);
+                inner.reused.fetch_add(1, Ordering::Relaxed);
+                if let Some(hooks) = &inner.hooks {
+                    hooks.on_reuse(class.size);
+                }
+                return (buf, true);
+            }
+        }
+        match class.pop() {
+            Some(buf) => {
+                let idle = class.idle.fetch_sub(1, Ordering::Relaxed) - 1;
+                class.low.fetch_min(idle, Ordering::Relaxed);
+                inner.cached.fetch_sub(1, Ordering::Relaxed);
+                inner.cached_bytes.fetch_sub(class.size, Ordering::Relaxed);
+                inner.reused.fetch_add(1, Ordering::Relaxed);
+                if let Some(hooks) = &inner.hooks {
+                    hooks.on_reuse(class.size);
+                }
+                (buf, true)
+            }
+            None => {
+                inner.misses.fetch_add(1, Ordering::Relaxed);
+                inner.allocated.fetch_add(1, Ordering::Relaxed);
+                (self.allocate(class.size), false)
+            }
+        }
+    }
+
+    /// Hand `buf` out. With the `debug-pool` feature, this checks that nothing wrote to it while it
+    /// sat idle, and remembers it until it comes back.
+    fn lend(&self, buf: PoolBuf) -> PoolBuf {
+        #[cfg(feature = "debug-pool")]
+        let buf = {
+            let mut buf = buf;
+            buf.check_out();
+            if buf.capacity() != 0 {
+                self.inner.leases.lock().unwrap().insert(buf.addr());
+            }
+            buf
+        };
+        buf
+    }
+
+    /// Whether buffers of `class` go through the per-thread caches.
+    fn is_local(&self, class: &SizeClass) -> bool {
+        self.inner.config.thread_cache > 0 && class.size == self.buf_size()
+    }
+
+    /// Run `f` on the calling thread's cache for this pool, unless the thread is shutting down or
+    /// already inside it.
+    ///
+    /// No code outside the crate runs while the cache is borrowed: a [`BufferSource`] freeing
+    /// buffers may itself use the pool, and only ever finds the cache busy if `f` does.
+    fn with_local<T>(&self, f: impl FnOnce(&mut Vec<PoolBuf>) -> T) -> Option<T> {
+        LOCAL
+            .try_with(|local| {
+                let mut local = local.try_borrow_mut().ok()?;
+                let me = Arc::as_ptr(&self.inner);
+                if let Some(cache) = local.iter_mut().find(|cache| cache.pool.as_ptr() == me) {
+                    return Some(f(&mut cache.bufs));
+                }
+                // first use of this pool on this thread; forget pools that are gone meanwhile
+                let (live, dead): (Vec<_>, Vec<_>) = std::mem::take(&mut *local)
+                    .into_iter()
+                    .partition(|cache| cache.pool.strong_count() > 0);
+                *local = live;
+                local.push(LocalCache {
+                    pool: Arc::downgrade(&self.inner),
+                    bufs: Vec::new(),
+                });
+                let res = f(&mut local.last_mut()?.bufs);
+                drop(local);
+                drop(dead);
+                Some(res)
+            })
+            .ok()
+            .flatten()
+    }
+
+    fn allocate(&self, size: usize) -> PoolBuf {
+        if let Some(hooks) = &self.inner.hooks {
+            hooks.on_alloc(size);
+        }
+        let align = self.inner.config.alignment;
+        match &self.inner.source {
+            Some(source) => PoolBuf::in_source(size, align, source),
+            None => PoolBuf::with_capacity(size, align),
+        }
+    }
+
+    fn class_for(&self, len: usize) -> &SizeClass {
+        let classes = &self.inner.classes;
+        classes
+            .iter()
+            .find(|class| class.size >= len)
+            .unwrap_or(&classes[classes.len() - 1])
+    }
+
+    /// Like [`BufPool::take_tracked`], but reserve the buffer against the outstanding-bytes budget,
+    /// waiting for other buffers to come back if it is used up. The reservation is returned to the
+    /// budget with [`BufPool::release`].
+    pub(crate) fn poll_reserve(&self, cx: &mut Context<'_>, len: usize) -> Poll<(PoolBuf, bool)> {
+        let size = self.class_for(len).size;
+        if !self.try_reserve(size) {
+            // cloning can run the executor's code, which mustn't happen under the lock
+            let waker = cx.waker().clone();
+            let mut waiters = self.inner.waiters.lock().unwrap();
+            if !waiters.iter().any(|w| w.will_wake(&waker)) {
+                waiters.push(waker);
+            }
+            drop(waiters);
+            // a release between the first attempt and registering would otherwise go unnoticed
+            if !self.try_reserve(size) {
+                trace_event!(
+                    debug,
+                    size,
+                    outstanding = self.inner.outstanding.load(Ordering::Relaxed),
+                    max = self.inner.config.max_outstanding_bytes,
+                    "pool budget used up, waiting for buffers to come back"
+                );
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(self.take_tracked(len))
+    }
+
+    fn try_reserve(&self, size: usize) -> bool {
+        let max = self.inner.config.max_outstanding_bytes;
+        self.inner
+            .outstanding
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |out| {
+                (out == 0 || out.saturating_add(size) <= max).then(|| out.saturating_add(size))
+            })
+            .is_ok()
+    }
+
+    /// Give back `size` bytes reserved by [`BufPool::poll_reserve`], waking whoever waits on them.
+    pub(crate) fn release(&self, size: usize) {
+        if size == 0 {
+            return;
+        }
+        let inner = &self.inner;
+        inner.outstanding.fetch_sub(size, Ordering::Relaxed);
+        if inner.config.max_outstanding_bytes == usize::MAX {
+            return;
+        }
+        let waiters = std::mem::take(&mut *inner.waiters.lock().unwrap());
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+
+    /// Return a buffer to the class matching its capacity, unless the pool already caches as much
+    /// as it may. Buffers that match no class are simply freed. Returns whether it was cached.
+    pub(crate) fn give(&self, mut buf: PoolBuf) -> bool {
+        #[cfg(feature = "debug-pool")]
+        assert!(
+            buf.capacity() == 0 || self.inner.leases.lock().unwrap().remove(&buf.addr()),
+            "pooled buffer returned twice, or to a pool it didn't come from"
+        );
+        self.inner.in_use.fetch_sub(1, Ordering::Relaxed);
+        metrics::buffer_returned();
+        trace_event!(trace, size = buf.capacity(), "pooled buffer returned");
+        if let Some(hooks) = &self.inner.hooks {
+            hooks.on_release(buf.capacity());
+        }
+        if self.inner.config.zero_on_return {
+            buf.zeroize();
+        }
+        #[cfg(feature = "debug-pool")]
+        buf.check_in();
+        let Some(class) = self
+            .inner
+            .classes
+            .iter()
+            .find(|class| class.size == buf.capacity())
+            .filter(|_| buf.align() == self.inner.config.alignment)
+        else {
+            return false;
+        };
+        let inner = &self.inner;
+        let count = inner.cached.fetch_add(1, Ordering::Relaxed);
+        let bytes = inner.cached_bytes.fetch_add(class.size, Ordering::Relaxed);
+        let cached = count < inner.config.max_cached_buffers
+            && bytes.saturating_add(class.size) <= inner.config.max_total_bytes;
+        if cached {
+            let mut slot = Some(buf);
+            if self.is_local(class) {
+                self.with_local(|bufs| {
+                    if bufs.len() < inner.config.thread_cache {
+                        bufs.extend(slot.take());
+                    }
+                });
+            }
+            if let Some(buf) = slot {
+                class.idle.fetch_add(1, Ordering::Relaxed);
+                class.push(buf);
+            }
+            inner
+                .peak_cached_bytes
+                .fetch_max(bytes + class.size, Ordering::Relaxed);
+        } else {
+            inner.cached.fetch_sub(1, Ordering::Relaxed);
+            inner.cached_bytes.fetch_sub(class.size, Ordering::Relaxed);
+            inner.discarded.fetch_add(1, Ordering::Relaxed);
+            trace_event!(
+                trace,
+                size = class.size,
+                "pool at its caps, freeing returned buffer"
+            );
+        }
+        metrics::buffer_kept(class.size, cached);
+        if let Some(timeout) = inner.config.idle_timeout {
+            self.maybe_sweep(timeout);
+        }
+        cached
+    }
+
+    /// Once per `timeout`, free the buffers each class had idle throughout the last period.
+    ///
+    /// Whichever `give` first notices the deadline has passed does the sweep; the others carry on.
+    fn maybe_sweep(&self, timeout: Duration) {
+        let inner = &self.inner;
+        let now = nanos(inner.created.elapsed());
+        let deadline = inner.next_sweep.load(Ordering::Relaxed);
+        if now < deadline
+            || inner
+                .next_sweep
+                .compare_exchange(
+                    deadline,
+                    now.saturating_add(nanos(timeout)),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+        {
+            return;
+        }
+        for class in inner.classes.iter() {
+            let unneeded = class
+                .low
+                .swap(class.idle.load(Ordering::Relaxed), Ordering::Relaxed);
+            for _ in 0..unneeded {
+                if !self.evict_one(class) {
+                    break;
+                }
+            }
+            // what's left is the starting point for the next period
+            class
+                .low
+                .fetch_min(class.idle.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+
+    /// Pop and free one idle buffer of `class`, if it has any.
+    fn evict_one(&self, class: &SizeClass) -> bool {
+        let Some(buf) = class.pop() else {
+            return false;
+        };
+        drop(buf);
+        let inner = &self.inner;
+        class.idle.fetch_sub(1, Ordering::Relaxed);
+        inner.cached.fetch_sub(1, Ordering::Relaxed);
+        inner.cached_bytes.fetch_sub(class.size, Ordering::Relaxed);
+        inner.evicted.fetch_add(1, Ordering::Relaxed);
+        metrics::buffers_evicted(1, class.size);
+        true
+    }
+}
+
+/// Sets up a [`BufPool`] with extras beyond its [`BufPoolConfig`], from [`BufPool::builder`].
+pub struct BufPoolBuilder {
+    config: BufPoolConfig,
+    source: Option<Arc<dyn BufferSource>>,
+    hooks: Option<Box<dyn PoolHooks>>,
+}
+
+impl BufPoolBuilder {
+    /// Get buffer memory from `source` instead of the global allocator.
+    pub fn source(mut self, source: impl BufferSource) -> Self {
+        self.source = Some(Arc::new(source));
+        self
+    }
+
+    /// Have the pool report buffers being allocated, reused and returned to `hooks`.
+    pub fn hooks(mut self, hooks: impl PoolHooks) -> Self {
+        self.hooks = Some(Box::new(hooks));
+        self
+    }
+
+    pub fn build(self) -> BufPool {
+        BufPool::build(self.config, self.source, self.hooks)
+    }
+}
+
+impl std::fmt::Debug for BufPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufPool")
+            .field("buf_size", &self.buf_size())
+            .field("classes", &self.classes().collect::<Vec<_>>())
+            .field("capacity", &self.capacity())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The pool a buffer goes back to. The global pool is referred to without a refcount, so the
+/// default paths never touch a shared counter per buffer.
+#[derive(Clone)]
+pub(crate) enum PoolRef {
+    Global,
+    Owned(BufPool),
+}
+
+impl PoolRef {
+    pub(crate) fn get(&self) -> &BufPool {
+        match self {
+            PoolRef::Global => BufPool::global(),
+            PoolRef::Owned(pool) => pool,
+        }
+    }
+
+    /// Take a buffer for a read of `len` bytes that will be held across polls, counting it
+    /// against the pool's outstanding-bytes budget until the guard drops.
+    pub(crate) fn poll_acquire(&self, cx: &mut Context<'_>, len: usize) -> Poll<(BufGuard, bool)> {
+        let (buf, reused) = std::task::ready!(self.get().poll_reserve(cx, len));
+        let size = buf.capacity();
+        Poll::Ready((BufGuard::new(buf, self.clone(), size), reused))
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos().try_into().unwrap_or(u64::MAX)
+}
@@ -0,0 +1,86 @@
+use std::pin::Pin;
+
+use futures_util::{future::poll_fn, AsyncRead};
+
+use crate::{init_buf, pool::PoolRef, retry_interrupted, BufGuard};
+
+/// Bytes read into a pooled buffer, which goes back to the pool when this is dropped.
+pub struct PooledBytes {
+    buf: BufGuard,
+    len: usize,
+}
+
+impl PooledBytes {
+    /// Wrap the first `len` bytes of a pooled buffer.
+    pub(crate) fn new(buf: BufGuard, len: usize) -> Self {
+        Self { buf, len }
+    }
+
+    /// Read once from `rdr` into a buffer from `pool`.
+    pub(crate) async fn read_from(
+        pool: PoolRef,
+        mut rdr: impl AsyncRead + Unpin,
+    ) -> Result<PooledBytes, std::io::Error> {
+        poll_fn(|cx| {
+            let pool_size = pool.get().buf_size();
+            let (mut free_buf, _) = std::task::ready!(pool.poll_acquire(cx, pool_size));
+            let buf = init_buf(&mut free_buf, pool_size);
+            match retry_interrupted(|| Pin::new(&mut rdr).poll_read(cx, buf)) {
+                std::task::Poll::Ready(Ok(n)) => {
+                    std::task::Poll::Ready(Ok(PooledBytes::new(free_buf, n)))
+                }
+                std::task::Poll::Ready(Err(err)) => std::task::Poll::Ready(Err(err)),
+                std::task::Poll::Pending => std::task::Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Copy the contents out into a standalone `Bytes`.
+    ///
+    /// Converting with `Bytes::from` instead avoids the copy, at the cost of keeping the whole
+    /// pooled buffer alive for as long as the `Bytes` is.
+    pub fn to_bytes(&self) -> bytes::Bytes {
+        bytes::Bytes::copy_from_slice(self)
+    }
+}
+
+impl From<PooledBytes> for bytes::Bytes {
+    /// Hand the pooled buffer over without copying; it goes back to the pool once the last clone
+    /// of the `Bytes` is dropped. Empty reads come back right away.
+    fn from(bytes: PooledBytes) -> Self {
+        if bytes.is_empty() {
+            return bytes::Bytes::new();
+        }
+        bytes::Bytes::from_owner(bytes)
+    }
+}
+
+impl std::ops::Deref for PooledBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl AsRef<[u8]> for PooledBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl std::fmt::Debug for PooledBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PooledBytes").field(&&self[..]).finish()
+    }
+}
+
+/// Like [`crate::pooled_read`], but hands back the pooled buffer itself instead of a fresh allocation.
+///
+/// The buffer is recycled once the returned [`PooledBytes`] is dropped.
+pub async fn pooled_read_recycled(
+    rdr: impl AsyncRead + Unpin,
+) -> Result<PooledBytes, std::io::Error> {
+    PooledBytes::read_from(PoolRef::Global, rdr).await
+}
@@ -0,0 +1,100 @@
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+use crate::trim_under_pressure;
+
+/// Stall time, then window length, in microseconds: a trim whenever some task waited on memory
+/// for 150 ms out of 2 s. Unprivileged processes may only ask for windows in whole seconds that
+/// are a multiple of 2.
+const TRIGGER: &str = "some 150000 2000000";
+
+/// Start a background thread that calls [`trim_under_pressure`] whenever the kernel reports
+/// memory pressure, so a containerized process gives up its idle buffers before it runs into its
+/// memory limit instead of getting OOM-killed while hoarding them.
+///
+/// The thread watches pressure stall information (PSI) for the process's own cgroup, falling
+/// back to the system-wide figures outside a cgroup v2 hierarchy. Fails if neither is available,
+/// which is the case before Linux 4.20 or with PSI disabled. The thread runs until the process
+/// exits; call this once.
+pub fn spawn_pressure_trimmer() -> Result<(), std::io::Error> {
+    let fd = open_trigger()?;
+    std::thread::Builder::new()
+        .name("bufpool-pressure".into())
+        .spawn(move || watch(fd))?;
+    Ok(())
+}
+
+/// The `memory.pressure` file of the cgroup we're in, if we're in a cgroup v2 hierarchy.
+fn cgroup_pressure_path() -> Option<String> {
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    let path = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+    Some(format!(
+        "/sys/fs/cgroup{}/memory.pressure",
+        path.trim_end_matches('/')
+    ))
+}
+
+/// Register a PSI trigger, which the returned file signals with `POLLPRI` every time it fires.
+fn open_trigger() -> Result<OwnedFd, std::io::Error> {
+    let mut last_err = std::io::Error::from(std::io::ErrorKind::NotFound);
+    for path in cgroup_pressure_path()
+        .into_iter()
+        .chain(["/proc/pressure/memory".into()])
+    {
+        match arm(&path) {
+            Ok(fd) => return Ok(fd),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+fn arm(path: &str) -> Result<OwnedFd, std::io::Error> {
+    let path = CString::new(path).map_err(std::io::Error::other)?;
+    // SAFETY: a valid C string, and the descriptor is owned right away
+    let fd = unsafe {
+        libc::open(
+            path.as_ptr(),
+            libc::O_RDWR | libc::O_NONBLOCK | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: just opened, and nothing else has it
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    // the trigger has to arrive in a single write, including its terminating NUL
+    let trigger = CString::new(TRIGGER).unwrap();
+    let bytes = trigger.as_bytes_with_nul();
+    // SAFETY: writing from a live buffer of that length
+    let written = unsafe { libc::write(fd.as_raw_fd(), bytes.as_ptr().cast(), bytes.len()) };
+    if written < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+fn watch(fd: OwnedFd) {
+    let mut poll = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLPRI,
+        revents: 0,
+    };
+    loop {
+        // SAFETY: one valid pollfd
+        let n = unsafe { libc::poll(&mut poll, 1, -1) };
+        if n < 0 {
+            if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return;
+        }
+        if poll.revents & libc::POLLERR != 0 {
+            // the cgroup went away
+            return;
+        }
+        if poll.revents & libc::POLLPRI != 0 {
+            trim_under_pressure();
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_timer::Delay;
+use futures_util::{AsyncRead, AsyncWrite};
+
+use crate::time::Instant;
+
+/// A token bucket holding up to one second's worth of bytes, unless built with a different burst.
+pub(crate) struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+    delay: Option<Delay>,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        Self::with_burst(bytes_per_sec, bytes_per_sec)
+    }
+
+    /// A bucket refilling at `bytes_per_sec` that holds up to `burst` bytes, starting full.
+    pub(crate) fn with_burst(bytes_per_sec: u64, burst: u64) -> Self {
+        let burst = burst.max(1) as f64;
+        Self {
+            rate: bytes_per_sec.max(1) as f64,
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+            delay: None,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+    }
+
+    /// Wait until a reasonably sized chunk (up to `want` bytes) may be transferred, returning how
+    /// many bytes are currently allowed.
+    ///
+    /// Nothing is deducted until [`TokenBucket::consume`] is called with what was actually moved.
+    pub(crate) fn poll_available(&mut self, cx: &mut Context<'_>, want: usize) -> Poll<usize> {
+        loop {
+            if let Some(delay) = &mut self.delay {
+                std::task::ready!(Pin::new(delay).poll(cx));
+                self.delay = None;
+            }
+            match self.check(want) {
+                Ok(n) => return Poll::Ready(n),
+                Err(wait) => self.delay = Some(Delay::new(wait)),
+            }
+        }
+    }
+
+    /// How many bytes (up to `want`) may be transferred right now, or how long until enough may.
+    fn check(&mut self, want: usize) -> Result<usize, Duration> {
+        self.refill();
+        // wait for a chunk or a tenth of the burst, rather than single bytes, so that wakeups
+        // stay rare without overshooting on the last short chunk
+        let need = (want as f64).min(self.burst / 10.0).max(1.0);
+        if self.tokens >= need {
+            return Ok((self.tokens as usize).min(want));
+        }
+        Err(Duration::from_secs_f64((need - self.tokens) / self.rate))
+    }
+
+    pub(crate) fn consume(&mut self, n: usize) {
+        self.tokens -= n as f64;
+    }
+}
+
+/// A bytes-per-second budget that any number of [`Throttled`] streams can draw from together.
+///
+/// Clones share the same budget, so e.g. all connections of one client can be held to a single
+/// limit between them. Each stream waits on its own timer, so none of them starves the others of
+/// wakeups.
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// Allow roughly `bytes_per_sec`, with bursts of up to one second's worth.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self::with_burst(bytes_per_sec, bytes_per_sec)
+    }
+
+    /// Allow roughly `bytes_per_sec`, with up to `burst` bytes going through at once after a
+    /// quiet spell.
+    pub fn with_burst(bytes_per_sec: u64, burst: u64) -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(TokenBucket::with_burst(bytes_per_sec, burst))),
+        }
+    }
+
+    /// Wait until up to `want` bytes may go through, using `delay` as this stream's timer.
+    fn poll_available(
+        &self,
+        cx: &mut Context<'_>,
+        delay: &mut Option<Delay>,
+        want: usize,
+    ) -> Poll<usize> {
+        loop {
+            if let Some(timer) = delay {
+                std::task::ready!(Pin::new(timer).poll(cx));
+                *delay = None;
+            }
+            match self.bucket.lock().unwrap().check(want) {
+                Ok(n) => return Poll::Ready(n),
+                Err(wait) => *delay = Some(Delay::new(wait)),
+            }
+        }
+    }
+
+    fn consume(&self, n: usize) {
+        self.bucket.lock().unwrap().consume(n);
+    }
+}
+
+/// A reader or writer throttled to a [`RateLimiter`]'s budget, for limits outside a single copy
+/// (for those, see [`crate::PooledCopy::rate_limit`]).
+///
+/// Each read or write is cut down to what the budget allows at that moment, waiting first if it
+/// allows nothing. Flushing and closing aren't throttled.
+pub struct Throttled<T> {
+    inner: T,
+    limiter: RateLimiter,
+    delay: Option<Delay>,
+}
+
+impl<T> Throttled<T> {
+    /// Throttle `inner` to roughly `bytes_per_sec` on its own.
+    pub fn new(inner: T, bytes_per_sec: u64) -> Self {
+        Self::with_limiter(inner, RateLimiter::new(bytes_per_sec))
+    }
+
+    /// Throttle `inner` to a budget possibly shared with other streams.
+    pub fn with_limiter(inner: T, limiter: RateLimiter) -> Self {
+        Self {
+            inner,
+            limiter,
+            delay: None,
+        }
+    }
+
+    pub fn limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Throttled<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let this = &mut *self;
+        let allowed =
+            std::task::ready!(this.limiter.poll_available(cx, &mut this.delay, buf.len()));
+        let n = std::task::ready!(Pin::new(&mut this.inner).poll_read(cx, &mut buf[..allowed]))?;
+        this.limiter.consume(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Throttled<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        if buf.is_empty() {
+            return Pin::new(&mut self.inner).poll_write(cx, buf);
+        }
+        let this = &mut *self;
+        let allowed =
+            std::task::ready!(this.limiter.poll_available(cx, &mut this.delay, buf.len()));
+        let n = std::task::ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..allowed]))?;
+        this.limiter.consume(n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
@@ -0,0 +1,743 @@
+use std::{future::Future, io::IoSliceMut, ops::ControlFlow, pin::Pin, time::Duration};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::{
+    future::poll_fn, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncSeek, AsyncSeekExt,
+};
+
+use crate::{
+    check_limit, chunk_size, init_buf, poll_pooled_read, pool::PoolRef, retry::Retrying,
+    retry_interrupted, time::Instant, BufGuard, BufPool, LimitExceeded, PooledBytes,
+    PooledOnceReader, RetryPolicy, BUF_SIZE,
+};
+
+/// Upper bound on the number of slices [`pooled_read_vectored`] splits its buffer into.
+pub const MAX_SEGMENTS: usize = 16;
+
+/// Like [`crate::pooled_read`], but splits the pooled buffer into `segments` slices (at most
+/// [`MAX_SEGMENTS`]) and reads through `poll_read_vectored`.
+///
+/// Readers without vectored support fall back to a plain read into the first slice.
+pub async fn pooled_read_vectored(
+    mut rdr: impl AsyncRead + Unpin,
+    segments: usize,
+) -> Result<Bytes, std::io::Error> {
+    let segments = segments.clamp(1, MAX_SEGMENTS);
+    poll_fn(|cx| {
+        let (mut free_buf, _) = std::task::ready!(PoolRef::Global.poll_acquire(cx, chunk_size()));
+        let res = {
+            let buf = init_buf(&mut free_buf, chunk_size());
+            let seg_len = buf.len().div_ceil(segments);
+            let mut chunks = buf.chunks_mut(seg_len);
+            let mut slices: [IoSliceMut<'_>; MAX_SEGMENTS] =
+                std::array::from_fn(|_| IoSliceMut::new(chunks.next().unwrap_or(&mut [])));
+            retry_interrupted(|| Pin::new(&mut rdr).poll_read_vectored(cx, &mut slices[..segments]))
+        };
+        res.map_ok(|n| PooledBytes::new(free_buf, n).into())
+    })
+    .await
+}
+
+/// Read exactly `n` bytes, allocating the result only once data starts arriving.
+///
+/// Fails with `UnexpectedEof` if the reader ends before `n` bytes were read.
+pub async fn pooled_read_exact(
+    mut rdr: impl AsyncRead + Unpin,
+    n: usize,
+) -> Result<Bytes, std::io::Error> {
+    let mut acc = BytesMut::new();
+    while acc.len() < n {
+        let want = n - acc.len();
+        let read = poll_fn(|cx| {
+            poll_pooled_read(Pin::new(&mut rdr), cx, want, |chunk| {
+                acc.reserve(want);
+                acc.extend_from_slice(chunk);
+                chunk.len()
+            })
+        })
+        .await?;
+        if read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+    Ok(acc.freeze())
+}
+
+/// Read until EOF into a single buffer, failing with [`LimitExceeded`] once more than `max_bytes`
+/// arrive. A zero `max_bytes` fails with `InvalidInput`, like the other limited reads.
+pub async fn pooled_read_to_end(
+    mut rdr: impl AsyncRead + Unpin,
+    max_bytes: usize,
+) -> Result<Bytes, std::io::Error> {
+    check_limit(max_bytes)?;
+    let mut acc = BytesMut::new();
+    loop {
+        // ask for one byte past the cap so that overflowing streams are detected
+        let want = (max_bytes - acc.len()).saturating_add(1).min(chunk_size());
+        let read = poll_fn(|cx| {
+            poll_pooled_read(Pin::new(&mut rdr), cx, want, |chunk| {
+                acc.extend_from_slice(chunk);
+                chunk.len()
+            })
+        })
+        .await?;
+        if read == 0 {
+            return Ok(acc.freeze());
+        }
+        if acc.len() > max_bytes {
+            return Err(LimitExceeded { limit: max_bytes }.into());
+        }
+    }
+}
+
+/// Read up to and including the next `delim` byte, stopping early after `limit` bytes or at EOF.
+///
+/// This takes an [`AsyncBufRead`] so that bytes past the delimiter stay in the reader. The
+/// reader's own buffer stands in for a pooled one: it is searched in place, and the result is
+/// copied out of it once, unless the delimiter only turns up after more reads. A zero `limit`
+/// fails with `InvalidInput` rather than looking like EOF.
+pub async fn pooled_read_until(
+    mut rdr: impl AsyncBufRead + Unpin,
+    delim: u8,
+    limit: usize,
+) -> Result<Bytes, std::io::Error> {
+    check_limit(limit)?;
+    let mut acc = BytesMut::new();
+    loop {
+        let avail = match rdr.fill_buf().await {
+            Ok(avail) => avail,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if avail.is_empty() {
+            return Ok(acc.freeze());
+        }
+        let window = &avail[..avail.len().min(limit - acc.len())];
+        let (used, done) = match window.iter().position(|b| *b == delim) {
+            Some(idx) => (idx + 1, true),
+            None => (window.len(), acc.len() + window.len() == limit),
+        };
+        if done && acc.is_empty() {
+            // the common case: the whole thing was already buffered
+            let out = Bytes::copy_from_slice(&window[..used]);
+            rdr.consume_unpin(used);
+            return Ok(out);
+        }
+        acc.extend_from_slice(&window[..used]);
+        rdr.consume_unpin(used);
+        if done {
+            return Ok(acc.freeze());
+        }
+    }
+}
+
+/// Read one line as UTF-8, without its `\n` or `\r\n` terminator, returning `None` at EOF.
+///
+/// Lines longer than `max_len` bytes fail with [`LimitExceeded`].
+pub async fn pooled_read_line(
+    rdr: impl AsyncBufRead + Unpin,
+    max_len: usize,
+) -> Result<Option<String>, std::io::Error> {
+    // leave room for a CRLF terminator on a line that is exactly max_len long
+    let limit = max_len.saturating_add(2);
+    let line = pooled_read_until(rdr, b'\n', limit).await?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let mut line = Vec::from(line);
+    let terminated = line.last() == Some(&b'\n');
+    if terminated {
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+    }
+    if line.len() > max_len || (!terminated && line.len() == limit) {
+        return Err(LimitExceeded { limit: max_len }.into());
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Seek to `offset` and do a single pooled read of at most `limit` bytes there.
+///
+/// The buffer comes from the smallest size class that fits `limit`, so a single read is capped at
+/// the largest class (1 MiB by default). A zero `limit` fails with `InvalidInput` rather than
+/// looking like EOF.
+pub async fn pooled_read_at<R: AsyncRead + AsyncSeek + Unpin>(
+    mut rdr: R,
+    offset: u64,
+    limit: usize,
+) -> Result<Bytes, std::io::Error> {
+    check_limit(limit)?;
+    rdr.seek(std::io::SeekFrom::Start(offset)).await?;
+    poll_fn(|cx| poll_pooled_read(Pin::new(&mut rdr), cx, limit, Bytes::copy_from_slice)).await
+}
+
+/// Like [`crate::pooled_read`], but fails with `TimedOut` if the reader is not ready within `timeout`.
+pub async fn pooled_read_timeout(
+    rdr: impl AsyncRead + Unpin,
+    timeout: Duration,
+) -> Result<Bytes, std::io::Error> {
+    with_timeout(PooledOnceReader(rdr, true), timeout).await
+}
+
+/// Like [`crate::pooled_read`], but fails with `TimedOut` if the reader is not ready by `deadline`.
+pub async fn pooled_read_with_deadline(
+    rdr: impl AsyncRead + Unpin,
+    deadline: Instant,
+) -> Result<Bytes, std::io::Error> {
+    with_deadline(PooledOnceReader(rdr, true), deadline).await
+}
+
+/// Like [`pooled_read_exact`], but fails with `TimedOut` if all `n` bytes haven't arrived by
+/// `deadline`, however many chunks it takes.
+pub async fn pooled_read_exact_with_deadline(
+    rdr: impl AsyncRead + Unpin,
+    n: usize,
+    deadline: Instant,
+) -> Result<Bytes, std::io::Error> {
+    with_deadline(pooled_read_exact(rdr, n), deadline).await
+}
+
+/// Like [`crate::pooled_read`], but retrying a read that fails with an error `policy` covers.
+pub async fn pooled_read_with_retry(
+    mut rdr: impl AsyncRead + Unpin,
+    policy: RetryPolicy,
+) -> Result<Bytes, std::io::Error> {
+    let mut retry = Retrying::new(policy);
+    loop {
+        poll_fn(|cx| retry.poll_wait(cx)).await;
+        match PooledOnceReader(&mut rdr, true).await {
+            Err(err) if retry.schedule(&err) => {}
+            res => return res,
+        }
+    }
+}
+
+/// Run `op`, failing with `TimedOut` if it hasn't finished by `deadline`.
+async fn with_deadline<T>(
+    op: impl Future<Output = Result<T, std::io::Error>>,
+    deadline: Instant,
+) -> Result<T, std::io::Error> {
+    with_timeout(op, deadline.saturating_duration_since(Instant::now())).await
+}
+
+/// Run `op`, failing with `TimedOut` if it hasn't finished within `timeout`.
+async fn with_timeout<T>(
+    op: impl Future<Output = Result<T, std::io::Error>>,
+    timeout: Duration,
+) -> Result<T, std::io::Error> {
+    let mut op = std::pin::pin!(op);
+    let mut delay = futures_timer::Delay::new(timeout);
+    poll_fn(|cx| {
+        if let std::task::Poll::Ready(res) = op.as_mut().poll(cx) {
+            return std::task::Poll::Ready(res);
+        }
+        match Pin::new(&mut delay).poll(cx) {
+            std::task::Poll::Ready(()) => {
+                trace_event!(debug, "pooled read timed out");
+                std::task::Poll::Ready(Err(std::io::ErrorKind::TimedOut.into()))
+            }
+            std::task::Poll::Pending => std::task::Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// Keep reading until at least `min` bytes (but never more than `max`) have arrived, or the reader hits EOF.
+///
+/// A `min` larger than `max` is treated as `max`, and a zero `max` fails with `InvalidInput`.
+pub async fn pooled_read_min(
+    mut rdr: impl AsyncRead + Unpin,
+    min: usize,
+    max: usize,
+) -> Result<Bytes, std::io::Error> {
+    check_limit(max)?;
+    let min = min.min(max);
+    let mut acc = BytesMut::with_capacity(min);
+    while acc.len() < min {
+        let want = (max - acc.len()).min(chunk_size());
+        let read = poll_fn(|cx| {
+            poll_pooled_read(Pin::new(&mut rdr), cx, want, |chunk| {
+                acc.extend_from_slice(chunk);
+                chunk.len()
+            })
+        })
+        .await?;
+        if read == 0 {
+            break;
+        }
+    }
+    Ok(acc.freeze())
+}
+
+/// Look at up to `limit` buffered bytes through `f` without consuming them.
+///
+/// This resolves as soon as the reader has any data buffered, so `f` may see fewer than `limit`
+/// bytes; an empty slice means EOF. A zero `limit` fails with `InvalidInput` for that reason.
+pub async fn pooled_peek<T>(
+    mut rdr: impl AsyncBufRead + Unpin,
+    limit: usize,
+    f: impl FnOnce(&[u8]) -> T,
+) -> Result<T, std::io::Error> {
+    check_limit(limit)?;
+    loop {
+        match rdr.fill_buf().await {
+            Ok(avail) => return Ok(f(&avail[..avail.len().min(limit)])),
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Read exactly `N` bytes into a stack array, without touching the heap.
+///
+/// Returns `None` on a clean EOF before the first byte, and `UnexpectedEof` if the stream ends partway.
+pub async fn pooled_read_array<const N: usize>(
+    mut rdr: impl AsyncRead + Unpin,
+) -> Result<Option<[u8; N]>, std::io::Error> {
+    let mut arr = [0u8; N];
+    let mut filled = 0;
+    while filled < N {
+        let n = poll_fn(|cx| {
+            retry_interrupted(|| Pin::new(&mut rdr).poll_read(cx, &mut arr[filled..]))
+        })
+        .await?;
+        if n == 0 {
+            if filled == 0 {
+                return Ok(None);
+            }
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        filled += n;
+    }
+    Ok(Some(arr))
+}
+
+/// Do a single pooled read of at most `limit` bytes, appending the result to `dst`.
+///
+/// As with [`pooled_read_at`], the buffer comes from the smallest size class that fits `limit`.
+/// Returns how many bytes were appended; zero means EOF. A zero `limit`, or a `dst` with no room
+/// left, fails with `InvalidInput` instead.
+pub async fn pooled_read_into(
+    mut rdr: impl AsyncRead + Unpin,
+    dst: &mut impl BufMut,
+    limit: usize,
+) -> Result<usize, std::io::Error> {
+    let limit = limit.min(dst.remaining_mut());
+    check_limit(limit)?;
+    poll_fn(|cx| {
+        poll_pooled_read(Pin::new(&mut rdr), cx, limit, |chunk| {
+            dst.put_slice(chunk);
+            chunk.len()
+        })
+    })
+    .await
+}
+
+/// Feed successive pooled chunks into `f` until it returns `Break` or the reader hits EOF.
+///
+/// Chunks are only borrowed for the duration of the call, so nothing is allocated per chunk.
+pub async fn pooled_read_fold<A>(
+    mut rdr: impl AsyncRead + Unpin,
+    init: A,
+    mut f: impl FnMut(A, &[u8]) -> ControlFlow<A, A>,
+) -> Result<A, std::io::Error> {
+    let mut acc = Some(init);
+    loop {
+        let step = poll_fn(|cx| {
+            poll_pooled_read(Pin::new(&mut rdr), cx, chunk_size(), |chunk| {
+                if chunk.is_empty() {
+                    return None;
+                }
+                let prev = acc.take().expect("fold accumulator already taken");
+                Some(f(prev, chunk))
+            })
+        })
+        .await?;
+        match step {
+            None => return Ok(acc.take().expect("fold accumulator already taken")),
+            Some(ControlFlow::Break(done)) => return Ok(done),
+            Some(ControlFlow::Continue(next)) => acc = Some(next),
+        }
+    }
+}
+
+/// The verdict of a [`pooled_read_parse`] callback on the bytes seen so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Parsed<T> {
+    /// A value was parsed out of the first `usize` bytes.
+    Done(T, usize),
+    /// The bytes so far are an incomplete prefix; keep reading.
+    NeedMore,
+}
+
+/// Drive an incremental parser, re-invoking `f` with everything accumulated so far until it returns
+/// [`Parsed::Done`].
+///
+/// Returns the parsed value together with any bytes read past the consumed prefix. Fails with
+/// [`LimitExceeded`] if `limit` bytes accumulate without a result, and `UnexpectedEof` if the
+/// reader ends first. A zero `limit` fails with `InvalidInput`.
+pub async fn pooled_read_parse<T>(
+    mut rdr: impl AsyncRead + Unpin,
+    limit: usize,
+    mut f: impl FnMut(&[u8]) -> Parsed<T>,
+) -> Result<(T, Bytes), std::io::Error> {
+    check_limit(limit)?;
+    let mut acc = BytesMut::new();
+    loop {
+        if acc.len() >= limit {
+            return Err(LimitExceeded { limit }.into());
+        }
+        let want = (limit - acc.len()).min(chunk_size());
+        let step = poll_fn(|cx| {
+            poll_pooled_read(Pin::new(&mut rdr), cx, want, |chunk| {
+                if chunk.is_empty() {
+                    return None;
+                }
+                if acc.is_empty() {
+                    // try the pooled chunk directly before copying anything out of it
+                    if let Parsed::Done(value, used) = f(chunk) {
+                        let used = used.min(chunk.len());
+                        return Some(Some((value, Bytes::copy_from_slice(&chunk[used..]))));
+                    }
+                    acc.extend_from_slice(chunk);
+                    return Some(None);
+                }
+                acc.extend_from_slice(chunk);
+                match f(&acc) {
+                    Parsed::Done(value, used) => {
+                        let rest = acc.split_off(used.min(acc.len()));
+                        Some(Some((value, rest.freeze())))
+                    }
+                    Parsed::NeedMore => Some(None),
+                }
+            })
+        })
+        .await?;
+        match step {
+            None => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Some(Some(done)) => return Ok(done),
+            Some(None) => {}
+        }
+    }
+}
+
+/// Read and discard up to `n` bytes through the pool, returning how many were skipped before EOF.
+pub async fn pooled_skip(mut rdr: impl AsyncRead + Unpin, n: u64) -> Result<u64, std::io::Error> {
+    let mut skipped = 0u64;
+    while skipped < n {
+        let want = usize::try_from(n - skipped)
+            .unwrap_or(usize::MAX)
+            .min(chunk_size());
+        let read =
+            poll_fn(|cx| poll_pooled_read(Pin::new(&mut rdr), cx, want, |chunk| chunk.len()))
+                .await?;
+        if read == 0 {
+            break;
+        }
+        skipped += read as u64;
+    }
+    Ok(skipped)
+}
+
+/// Read until EOF into a `String`, failing with [`LimitExceeded`] past `max_bytes`.
+///
+/// UTF-8 is validated chunk by chunk as data arrives, so code points split across reads are fine
+/// and invalid input is rejected without waiting for the rest of the stream. A zero `max_bytes`
+/// fails with `InvalidInput`.
+pub async fn pooled_read_to_string(
+    mut rdr: impl AsyncRead + Unpin,
+    max_bytes: usize,
+) -> Result<String, std::io::Error> {
+    check_limit(max_bytes)?;
+    let invalid = |e| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+    let mut acc = Vec::new();
+    // everything before this offset is known to be valid UTF-8
+    let mut valid = 0;
+    loop {
+        let want = (max_bytes - acc.len()).saturating_add(1).min(chunk_size());
+        let read = poll_fn(|cx| {
+            poll_pooled_read(Pin::new(&mut rdr), cx, want, |chunk| {
+                acc.extend_from_slice(chunk);
+                chunk.len()
+            })
+        })
+        .await?;
+        if read == 0 {
+            break;
+        }
+        if acc.len() > max_bytes {
+            return Err(LimitExceeded { limit: max_bytes }.into());
+        }
+        match std::str::from_utf8(&acc[valid..]) {
+            Ok(_) => valid = acc.len(),
+            // a code point cut off by the end of the chunk; the next read may complete it
+            Err(e) if e.error_len().is_none() => valid += e.valid_up_to(),
+            Err(e) => return Err(invalid(e)),
+        }
+    }
+    String::from_utf8(acc).map_err(|e| invalid(e.utf8_error()))
+}
+
+/// A buffered reader whose buffer is leased from the pool only while it holds unconsumed data.
+///
+/// A drop-in for `futures_util::io::BufReader`, except that an idle reader holds no buffer at all:
+/// a read that would block gives its buffer straight back, and so does consuming the last of what
+/// was buffered. A server with tens of thousands of mostly-quiet connections thus doesn't pin a
+/// buffer for each of them. Reads at least as big as the buffer skip it and go straight to the
+/// inner reader.
+pub struct PooledBufReader<R> {
+    inner: R,
+    buf: Option<BufGuard>,
+    pos: usize,
+    filled: usize,
+    capacity: usize,
+}
+
+impl<R: AsyncRead + Unpin> PooledBufReader<R> {
+    /// Wrap a reader with a pooled buffer of the global pool's buffer size, 8 KiB unless
+    /// configured otherwise.
+    pub fn new(inner: R) -> Self {
+        Self::with_capacity(chunk_size(), inner)
+    }
+
+    /// Wrap a reader, filling `capacity` bytes at a time (clamped to 8–64 KiB, and to the largest
+    /// buffer the global pool has), as with [`crate::PooledBufWriter::with_capacity`].
+    pub fn with_capacity(capacity: usize, inner: R) -> Self {
+        let largest = BufPool::global().classes().last().unwrap_or(BUF_SIZE);
+        Self {
+            inner,
+            buf: None,
+            pos: 0,
+            filled: 0,
+            capacity: capacity.clamp(BUF_SIZE, 64 << 10).min(largest),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwrap the reader, discarding any buffered data.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// The data read from the inner reader but not consumed yet.
+    pub fn buffer(&self) -> &[u8] {
+        match &self.buf {
+            Some(buf) => &buf[self.pos..self.filled],
+            None => &[],
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncBufRead for PooledBufReader<R> {
+    fn poll_fill_buf(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<&[u8], std::io::Error>> {
+        let this = self.get_mut();
+        if this.buf.is_none() {
+            let (mut free_buf, _) =
+                std::task::ready!(PoolRef::Global.poll_acquire(cx, this.capacity));
+            let buf = init_buf(&mut free_buf, this.capacity);
+            // a pending or failed read drops the buffer, which returns it
+            let n = std::task::ready!(retry_interrupted(|| {
+                Pin::new(&mut this.inner).poll_read(cx, buf)
+            }))?;
+            if n == 0 {
+                return std::task::Poll::Ready(Ok(&[]));
+            }
+            this.buf = Some(free_buf);
+            this.pos = 0;
+            this.filled = n;
+        }
+        std::task::Poll::Ready(Ok(this.buffer()))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.pos = (this.pos + amt).min(this.filled);
+        if this.pos == this.filled {
+            this.buf = None;
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for PooledBufReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        out: &mut [u8],
+    ) -> std::task::Poll<Result<usize, std::io::Error>> {
+        if self.buf.is_none() && out.len() >= self.capacity {
+            return Pin::new(&mut self.inner).poll_read(cx, out);
+        }
+        let avail = std::task::ready!(self.as_mut().poll_fill_buf(cx))?;
+        let n = avail.len().min(out.len());
+        out[..n].copy_from_slice(&avail[..n]);
+        self.consume(n);
+        std::task::Poll::Ready(Ok(n))
+    }
+}
+
+/// A reader that records what is read through it, up to a limit, so that protocol-sniffing code
+/// can look at the start of a stream and then [`Rewindable::rewind`] it for the real handler.
+///
+/// The recording goes into a single pooled buffer, taken on the first read and given back once
+/// the handler has read past it. Reading more than the limit while recording fails with
+/// [`LimitExceeded`]. Over an `AsyncBufRead` it is one too, so [`pooled_peek`] and the line
+/// readers work on it.
+pub struct Rewindable<R> {
+    inner: R,
+    record: Option<BufGuard>,
+    /// The read position within the recording.
+    pos: usize,
+    /// How much has been recorded.
+    len: usize,
+    limit: usize,
+    recording: bool,
+}
+
+impl<R: AsyncRead + Unpin> Rewindable<R> {
+    /// Record up to `limit` bytes (at least one, and at most the largest buffer the global pool
+    /// has) from the start of `inner`.
+    pub fn new(inner: R, limit: usize) -> Self {
+        let largest = BufPool::global().classes().last().unwrap_or(BUF_SIZE);
+        Self {
+            inner,
+            record: None,
+            pos: 0,
+            len: 0,
+            limit: limit.clamp(1, largest),
+            recording: true,
+        }
+    }
+
+    /// Go back to the start of the stream and stop recording, so the next reads replay what was
+    /// read so far and then carry on with the rest. Does nothing once recording has stopped.
+    pub fn rewind(&mut self) {
+        if self.recording {
+            self.recording = false;
+            self.pos = 0;
+            self.release_if_done();
+        }
+    }
+
+    /// Stop recording and carry on from where reading got to, without rewinding.
+    pub fn discard(&mut self) {
+        self.recording = false;
+        self.release_if_done();
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// What has been recorded so far, or what is left of it to replay after a rewind.
+    pub fn recorded(&self) -> &[u8] {
+        match &self.record {
+            Some(record) if self.recording => &record[..self.len],
+            Some(record) => &record[self.pos..self.len],
+            None => &[],
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Unwrap the reader, losing anything recorded but not replayed yet.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Give the recording back to the pool once it has all been replayed.
+    fn release_if_done(&mut self) {
+        if !self.recording && self.pos == self.len {
+            self.record = None;
+            self.pos = 0;
+            self.len = 0;
+        }
+    }
+
+    /// Read more of the stream onto the end of the recording, returning how much came in.
+    fn poll_record(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<usize, std::io::Error>> {
+        if self.record.is_none() {
+            let (record, _) = std::task::ready!(PoolRef::Global.poll_acquire(cx, self.limit));
+            self.record = Some(record);
+        }
+        if self.len == self.limit {
+            return std::task::Poll::Ready(Err(LimitExceeded { limit: self.limit }.into()));
+        }
+        let (len, limit) = (self.len, self.limit);
+        let buf = init_buf(self.record.as_mut().unwrap(), limit);
+        let n = std::task::ready!(retry_interrupted(|| {
+            Pin::new(&mut self.inner).poll_read(cx, &mut buf[len..])
+        }))?;
+        self.len += n;
+        std::task::Poll::Ready(Ok(n))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Rewindable<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        out: &mut [u8],
+    ) -> std::task::Poll<Result<usize, std::io::Error>> {
+        let this = &mut *self;
+        if this.pos == this.len {
+            if !this.recording {
+                return Pin::new(&mut this.inner).poll_read(cx, out);
+            }
+            if out.is_empty() || std::task::ready!(this.poll_record(cx))? == 0 {
+                return std::task::Poll::Ready(Ok(0));
+            }
+        }
+        let record = this.record.as_ref().unwrap();
+        let n = (this.len - this.pos).min(out.len());
+        out[..n].copy_from_slice(&record[this.pos..this.pos + n]);
+        this.pos += n;
+        this.release_if_done();
+        std::task::Poll::Ready(Ok(n))
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncBufRead for Rewindable<R> {
+    fn poll_fill_buf(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<&[u8], std::io::Error>> {
+        let this = self.get_mut();
+        if this.pos == this.len {
+            if !this.recording {
+                return Pin::new(&mut this.inner).poll_fill_buf(cx);
+            }
+            std::task::ready!(this.poll_record(cx))?;
+        }
+        let record = this.record.as_ref().unwrap();
+        std::task::Poll::Ready(Ok(&record[this.pos..this.len]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        if this.pos == this.len && !this.recording {
+            Pin::new(&mut this.inner).consume(amt);
+            return;
+        }
+        this.pos = (this.pos + amt).min(this.len);
+        this.release_if_done();
+    }
+}
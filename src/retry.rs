@@ -0,0 +1,103 @@
+use std::future::Future;
+use std::io::ErrorKind;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_timer::Delay;
+
+/// When reads and copies retry a failed operation on the same reader or writer, rather than
+/// failing, for sources where a hiccup like `ConnectionReset` can be waited out.
+///
+/// Attach one with [`crate::PooledCopy::retry`] or [`crate::pooled_read_with_retry`]. A copy keeps
+/// the chunks it has read but not written across retries, so nothing is lost or read twice.
+///
+/// Start from `RetryPolicy::default()` and override what needs tuning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries allowed in a row, after which the error is passed on. Any progress in between
+    /// starts the count over.
+    pub max_attempts: u32,
+    /// How long to wait before the first retry; each further one in a row waits twice as long.
+    pub initial_backoff: Duration,
+    /// The longest wait between retries.
+    pub max_backoff: Duration,
+    /// The error kinds worth retrying; anything else is passed on right away.
+    pub retryable: Vec<ErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    /// Three retries, backing off from 10 ms up to a second, on `ConnectionReset`,
+    /// `ConnectionAborted` and `TimedOut`.
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            retryable: vec![
+                ErrorKind::ConnectionReset,
+                ErrorKind::ConnectionAborted,
+                ErrorKind::TimedOut,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether the policy covers errors like `err` at all.
+    pub fn is_retryable(&self, err: &std::io::Error) -> bool {
+        self.retryable.contains(&err.kind())
+    }
+
+    /// The wait before retry number `attempt`, counting from zero.
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1 << attempt.min(31))
+            .min(self.max_backoff)
+    }
+}
+
+/// How far an operation has got through its [`RetryPolicy`].
+pub(crate) struct Retrying {
+    policy: RetryPolicy,
+    /// Retries since the last progress.
+    attempts: u32,
+    /// The backoff in progress, if any.
+    timer: Option<Delay>,
+}
+
+impl Retrying {
+    pub(crate) fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            attempts: 0,
+            timer: None,
+        }
+    }
+
+    /// Wait out the backoff, if one is in progress.
+    pub(crate) fn poll_wait(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(timer) = &mut self.timer {
+            std::task::ready!(Pin::new(timer).poll(cx));
+            self.timer = None;
+        }
+        Poll::Ready(())
+    }
+
+    /// Start a backoff if `err` should be retried, returning whether it should.
+    pub(crate) fn schedule(&mut self, err: &std::io::Error) -> bool {
+        if self.attempts >= self.policy.max_attempts || !self.policy.is_retryable(err) {
+            return false;
+        }
+        let backoff = self.policy.backoff(self.attempts);
+        trace_event!(debug, error = %err, attempt = self.attempts + 1, ?backoff, "retrying after a transient error");
+        self.timer = Some(Delay::new(backoff));
+        self.attempts += 1;
+        true
+    }
+
+    /// Note that the operation got somewhere, so the next failure starts the count over.
+    pub(crate) fn progressed(&mut self) {
+        self.attempts = 0;
+    }
+}
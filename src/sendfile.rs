@@ -0,0 +1,111 @@
+use std::fs::File;
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::FileExt;
+
+use futures_util::{future::poll_fn, AsyncWrite, AsyncWriteExt};
+
+use crate::{chunk_size, init_buf, pool::PoolRef, sys::retry_eintr};
+
+/// How much to hand the kernel per `sendfile` call.
+const SENDFILE_CHUNK: usize = 1 << 20;
+
+/// Send `file` from `offset` to EOF into a socket with `sendfile`, so the data never enters
+/// userspace. Returns the number of bytes sent.
+///
+/// The writer must be non-blocking and must not buffer internally, since its fd is used directly.
+/// Whenever the socket isn't ready, one chunk goes through a pooled buffer and the writer's async
+/// path instead, which is what waits for writability; if `sendfile` isn't supported for these
+/// fds at all, the whole transfer falls back to that pooled loop. The file's own position is left
+/// untouched.
+pub async fn pooled_sendfile<W>(
+    file: &File,
+    offset: u64,
+    writer: &mut W,
+) -> Result<u64, std::io::Error>
+where
+    W: AsyncWrite + AsRawFd + Unpin,
+{
+    let mut offset = offset;
+    let mut total = 0u64;
+    let mut kernel = true;
+    loop {
+        if kernel {
+            match sendfile(file.as_raw_fd(), writer.as_raw_fd(), offset, SENDFILE_CHUNK) {
+                Ok(0) => break,
+                Ok(n) => {
+                    offset += n as u64;
+                    total += n as u64;
+                    continue;
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(err) if unsupported(&err) => kernel = false,
+                Err(err) => return Err(err),
+            }
+        }
+        let n = copy_chunk(file, offset, writer).await?;
+        if n == 0 {
+            break;
+        }
+        offset += n;
+        total += n;
+    }
+    writer.flush().await?;
+    Ok(total)
+}
+
+fn unsupported(err: &std::io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EINVAL | libc::ENOSYS | libc::ENOTSOCK | libc::EOPNOTSUPP)
+    )
+}
+
+/// Relay one chunk of the file through a pooled buffer.
+async fn copy_chunk(
+    file: &File,
+    offset: u64,
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Result<u64, std::io::Error> {
+    let (mut free_buf, _) = poll_fn(|cx| PoolRef::Global.poll_acquire(cx, chunk_size())).await;
+    let buf = init_buf(&mut free_buf, chunk_size());
+    let n = loop {
+        match file.read_at(buf, offset) {
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            res => break res?,
+        }
+    };
+    writer.write_all(&buf[..n]).await?;
+    writer.flush().await?;
+    Ok(n as u64)
+}
+
+#[cfg(target_os = "linux")]
+fn sendfile(file: RawFd, sock: RawFd, offset: u64, len: usize) -> Result<usize, std::io::Error> {
+    let mut off = offset as libc::off_t;
+    retry_eintr(|| unsafe { libc::sendfile(sock, file, &mut off, len) })
+}
+
+#[cfg(target_os = "macos")]
+fn sendfile(file: RawFd, sock: RawFd, offset: u64, len: usize) -> Result<usize, std::io::Error> {
+    loop {
+        let mut sent = len as libc::off_t;
+        let res = unsafe {
+            libc::sendfile(
+                file,
+                sock,
+                offset as libc::off_t,
+                &mut sent,
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        // on EAGAIN and EINTR, `sent` still reports any partial progress
+        if res == 0 || sent > 0 {
+            return Ok(sent as usize);
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
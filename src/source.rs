@@ -0,0 +1,44 @@
+use std::alloc::Layout;
+use std::ptr::NonNull;
+
+/// Where a [`crate::BufPool`] gets the memory for its buffers, and where that memory goes when the
+/// pool frees a buffer for good.
+///
+/// Lets embedders back pooled buffers with an allocator arena, hugepages, pre-registered DMA
+/// regions, or a slab of their own; see [`crate::BufPool::with_source`]. Pools that aren't given
+/// one use [`SystemSource`]. Buffers are only allocated when the pool has no idle one to reuse, so
+/// a source sees a call per fresh buffer, not per read.
+///
+/// # Safety
+///
+/// A pointer returned by `allocate` must point to `layout.size()` bytes aligned to
+/// `layout.align()`, which nothing else uses until the pointer is passed back to `recycle` with
+/// the same layout.
+pub unsafe trait BufferSource: Send + Sync + 'static {
+    /// Provide memory for one buffer, or `None` to have the pool use the global allocator for it
+    /// instead.
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>>;
+
+    /// Take back memory handed out by [`BufferSource::allocate`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` came from `allocate` on this source with this `layout`, and is not used afterwards.
+    unsafe fn recycle(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+/// The global allocator, which pools use unless given another [`BufferSource`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemSource;
+
+// SAFETY: straight from the global allocator, with the layout it was asked for
+unsafe impl BufferSource for SystemSource {
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        // SAFETY: pools never ask for zero-sized buffers
+        NonNull::new(unsafe { std::alloc::alloc(layout) })
+    }
+
+    unsafe fn recycle(&self, ptr: NonNull<u8>, layout: Layout) {
+        std::alloc::dealloc(ptr.as_ptr(), layout)
+    }
+}
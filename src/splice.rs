@@ -0,0 +1,128 @@
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use futures_util::{future::poll_fn, AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    chunk_size, init_buf, pool::PoolRef, pooled_copy, pooled_read_recycled, sys::retry_eintr,
+    CopyError,
+};
+
+/// How much to move through the pipe per `splice` call.
+const SPLICE_CHUNK: usize = 64 << 10;
+
+/// Like [`pooled_copy`], but moves data between the two file descriptors in-kernel with `splice(2)`
+/// whenever possible, bypassing userspace buffers entirely.
+///
+/// Both sides must be non-blocking and must not buffer data internally, since the fds are used
+/// directly. Whenever the kernel path would block, that chunk instead goes through the pooled
+/// userspace path, which is what registers wakeups with the runtime; fds that `splice` doesn't
+/// support fall back to [`pooled_copy`] for the rest of the stream.
+pub async fn pooled_copy_splice<R, W>(reader: &mut R, writer: &mut W) -> Result<u64, CopyError>
+where
+    R: AsyncRead + AsRawFd + Unpin,
+    W: AsyncWrite + AsRawFd + Unpin,
+{
+    let (pipe_rd, pipe_wr) = pipe().map_err(CopyError::Read)?;
+    let mut total = 0u64;
+    loop {
+        let n = match splice(reader.as_raw_fd(), pipe_wr.as_raw_fd(), SPLICE_CHUNK) {
+            Ok(0) => {
+                writer.flush().await.map_err(CopyError::Flush)?;
+                return Ok(total);
+            }
+            Ok(n) => n,
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                // let the reader wait for readiness the normal way, and relay that one chunk
+                let chunk = pooled_read_recycled(&mut *reader)
+                    .await
+                    .map_err(CopyError::Read)?;
+                if chunk.is_empty() {
+                    writer.flush().await.map_err(CopyError::Flush)?;
+                    return Ok(total);
+                }
+                writer.write_all(&chunk).await.map_err(CopyError::Write)?;
+                writer.flush().await.map_err(CopyError::Flush)?;
+                total += chunk.len() as u64;
+                continue;
+            }
+            Err(err) if err.raw_os_error() == Some(libc::EINVAL) => {
+                return Ok(total + pooled_copy(reader, writer).await?);
+            }
+            Err(err) => return Err(CopyError::Read(err)),
+        };
+        let mut in_pipe = n;
+        while in_pipe > 0 {
+            match splice(pipe_rd.as_raw_fd(), writer.as_raw_fd(), in_pipe) {
+                Ok(0) => return Err(CopyError::Write(std::io::ErrorKind::WriteZero.into())),
+                Ok(n) => {
+                    in_pipe -= n;
+                    total += n as u64;
+                }
+                Err(err)
+                    if err.kind() == std::io::ErrorKind::WouldBlock
+                        || err.raw_os_error() == Some(libc::EINVAL) =>
+                {
+                    let unsupported = err.kind() != std::io::ErrorKind::WouldBlock;
+                    total += drain_pipe(&pipe_rd, writer, in_pipe).await?;
+                    in_pipe = 0;
+                    if unsupported {
+                        return Ok(total + pooled_copy(reader, writer).await?);
+                    }
+                }
+                Err(err) => return Err(CopyError::Write(err)),
+            }
+        }
+    }
+}
+
+/// Move what is sitting in our pipe out through the writer's async path, which waits for
+/// writability properly.
+async fn drain_pipe(
+    pipe_rd: &OwnedFd,
+    writer: &mut (impl AsyncWrite + Unpin),
+    mut in_pipe: usize,
+) -> Result<u64, CopyError> {
+    let (mut free_buf, _) = poll_fn(|cx| PoolRef::Global.poll_acquire(cx, chunk_size())).await;
+    let mut total = 0u64;
+    while in_pipe > 0 {
+        let buf = init_buf(&mut free_buf, chunk_size().min(in_pipe));
+        // our own pipe failing still means the read side of the copy is broken
+        let n = retry_eintr(|| unsafe {
+            libc::read(pipe_rd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len())
+        })
+        .map_err(CopyError::Read)?;
+        if n == 0 {
+            break;
+        }
+        writer
+            .write_all(&buf[..n])
+            .await
+            .map_err(CopyError::Write)?;
+        in_pipe -= n;
+        total += n as u64;
+    }
+    writer.flush().await.map_err(CopyError::Flush)?;
+    Ok(total)
+}
+
+fn pipe() -> Result<(OwnedFd, OwnedFd), std::io::Error> {
+    let mut fds = [0 as RawFd; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: pipe2 just handed us these two fds, and nothing else owns them
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+fn splice(from: RawFd, to: RawFd, len: usize) -> Result<usize, std::io::Error> {
+    retry_eintr(|| unsafe {
+        libc::splice(
+            from,
+            std::ptr::null_mut(),
+            to,
+            std::ptr::null_mut(),
+            len,
+            libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+        )
+    })
+}
@@ -0,0 +1,279 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::{AsyncRead, Stream};
+use memchr::memmem::Finder;
+
+use crate::{
+    chunk_size, init_buf, poll_pooled_read, pool::PoolRef, retry_interrupted, LimitExceeded,
+    PooledBytes,
+};
+
+/// Stream the contents of `reader` as chunks of at most `chunk_limit` bytes (at least 1), each one
+/// read into a pooled buffer.
+///
+/// The chunks are the pooled buffers themselves, as with [`crate::pooled_read`], and no buffer is
+/// held while the reader is blocked. The stream ends at EOF, or after yielding an error.
+/// `Interrupted` errors are retried.
+pub fn pooled_chunks<R: AsyncRead + Unpin>(reader: R, chunk_limit: usize) -> PooledChunks<R> {
+    PooledChunks {
+        reader,
+        limit: chunk_limit.max(1),
+        done: false,
+    }
+}
+
+/// The stream returned by [`pooled_chunks`].
+pub struct PooledChunks<R> {
+    reader: R,
+    limit: usize,
+    done: bool,
+}
+
+impl<R> PooledChunks<R> {
+    /// Get back the reader, positioned after the last chunk.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for PooledChunks<R> {
+    type Item = Result<Bytes, std::io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.done {
+            return Poll::Ready(None);
+        }
+        let (mut free_buf, _) = std::task::ready!(PoolRef::Global.poll_acquire(cx, this.limit));
+        let buf = init_buf(&mut free_buf, this.limit);
+        let res = std::task::ready!(retry_interrupted(|| {
+            Pin::new(&mut this.reader).poll_read(cx, buf)
+        }));
+        match res {
+            Ok(0) => {
+                this.done = true;
+                Poll::Ready(None)
+            }
+            Ok(n) => Poll::Ready(Some(Ok(PooledBytes::new(free_buf, n).into()))),
+            Err(err) => {
+                this.done = true;
+                Poll::Ready(Some(Err(err)))
+            }
+        }
+    }
+}
+
+/// Stream the lines of `reader` as UTF-8, without their `\n` or `\r\n` terminators, like
+/// `BufReader::lines` but with a cap on line length.
+///
+/// Reads go through pooled buffers that are returned right away; only the start of a line that
+/// hasn't fully arrived is kept in between. A line longer than `max_len` bytes yields a
+/// [`LimitExceeded`] error instead of growing without bound, and invalid UTF-8 an `InvalidData`
+/// one; either ends the stream. A last line without a terminator is still yielded.
+pub fn pooled_lines<R: AsyncRead + Unpin>(reader: R, max_len: usize) -> PooledLines<R> {
+    PooledLines {
+        reader,
+        max_len,
+        pending: Vec::new(),
+        searched: 0,
+        eof: false,
+        done: false,
+    }
+}
+
+/// The stream returned by [`pooled_lines`].
+pub struct PooledLines<R> {
+    reader: R,
+    max_len: usize,
+    /// Bytes read but not yielded yet.
+    pending: Vec<u8>,
+    /// How much of `pending` is known to hold no newline.
+    searched: usize,
+    eof: bool,
+    done: bool,
+}
+
+impl<R> PooledLines<R> {
+    /// Get back the reader. Bytes already read past the last line yielded are lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Take the first `len` bytes of `pending` as a line, dropping `skip` terminator bytes after
+    /// them.
+    fn take_line(&mut self, len: usize, skip: usize) -> Result<String, std::io::Error> {
+        let mut line: Vec<u8> = self.pending.drain(..len + skip).collect();
+        line.truncate(len);
+        if line.last() == Some(&b'\r') && skip > 0 {
+            line.pop();
+        }
+        self.searched = 0;
+        if self.pending.is_empty() && self.pending.capacity() > 2 * chunk_size() {
+            // don't keep the memory of an unusually long line around
+            self.pending = Vec::new();
+        }
+        if line.len() > self.max_len {
+            return Err(LimitExceeded {
+                limit: self.max_len,
+            }
+            .into());
+        }
+        String::from_utf8(line).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for PooledLines<R> {
+    type Item = Result<String, std::io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if this.done {
+                return Poll::Ready(None);
+            }
+            let line = match memchr::memchr(b'\n', &this.pending[this.searched..]) {
+                Some(idx) => Some(this.take_line(this.searched + idx, 1)),
+                None if this.eof && this.pending.is_empty() => {
+                    this.done = true;
+                    return Poll::Ready(None);
+                }
+                None if this.eof => Some(this.take_line(this.pending.len(), 0)),
+                // a `\r` may still turn out to be part of the terminator
+                None if this.pending.len() > this.max_len.saturating_add(1) => {
+                    Some(Err(LimitExceeded {
+                        limit: this.max_len,
+                    }
+                    .into()))
+                }
+                None => None,
+            };
+            if let Some(line) = line {
+                this.done |= line.is_err();
+                return Poll::Ready(Some(line));
+            }
+            this.searched = this.pending.len();
+            let pending = &mut this.pending;
+            let read = poll_pooled_read(Pin::new(&mut this.reader), cx, chunk_size(), |data| {
+                pending.extend_from_slice(data);
+                data.len()
+            });
+            match std::task::ready!(read) {
+                Ok(0) => this.eof = true,
+                Ok(_) => {}
+                Err(err) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
+            }
+        }
+    }
+}
+
+/// Stream the records of `reader` separated by `delimiter`, an arbitrary byte sequence like
+/// `b"\r\n--boundary"`, without the delimiters themselves.
+///
+/// Like [`pooled_lines`], reads go through pooled buffers, and only the part of a record that
+/// hasn't fully arrived is kept in between. Records are split off that one buffer rather than
+/// copied, and the search for the delimiter picks up where it left off rather than rescanning.
+/// A record longer than `max_record` bytes yields a [`LimitExceeded`] error, which ends the
+/// stream. Whatever follows the last delimiter is yielded as a final record, unless it's empty.
+///
+/// Panics if `delimiter` is empty.
+pub fn pooled_split<R: AsyncRead + Unpin>(
+    reader: R,
+    delimiter: &[u8],
+    max_record: usize,
+) -> PooledSplit<R> {
+    assert!(!delimiter.is_empty(), "empty delimiter");
+    PooledSplit {
+        reader,
+        finder: Finder::new(delimiter).into_owned(),
+        max_record,
+        pending: BytesMut::new(),
+        searched: 0,
+        eof: false,
+        done: false,
+    }
+}
+
+/// The stream returned by [`pooled_split`].
+pub struct PooledSplit<R> {
+    reader: R,
+    finder: Finder<'static>,
+    max_record: usize,
+    /// Bytes read but not yielded yet.
+    pending: BytesMut,
+    /// How far into `pending` the delimiter can't start.
+    searched: usize,
+    eof: bool,
+    done: bool,
+}
+
+impl<R> PooledSplit<R> {
+    /// Get back the reader. Bytes already read past the last record yielded are lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn limit_exceeded(&mut self) -> Option<Result<Bytes, std::io::Error>> {
+        self.done = true;
+        Some(Err(LimitExceeded {
+            limit: self.max_record,
+        }
+        .into()))
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for PooledSplit<R> {
+    type Item = Result<Bytes, std::io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let delim_len = this.finder.needle().len();
+        loop {
+            if this.done {
+                return Poll::Ready(None);
+            }
+            if let Some(idx) = this.finder.find(&this.pending[this.searched..]) {
+                let len = this.searched + idx;
+                if len > this.max_record {
+                    return Poll::Ready(this.limit_exceeded());
+                }
+                let record = this.pending.split_to(len).freeze();
+                this.pending.advance(delim_len);
+                this.searched = 0;
+                return Poll::Ready(Some(Ok(record)));
+            }
+            if this.eof {
+                this.done = true;
+                if this.pending.is_empty() {
+                    return Poll::Ready(None);
+                }
+                if this.pending.len() > this.max_record {
+                    return Poll::Ready(this.limit_exceeded());
+                }
+                return Poll::Ready(Some(Ok(std::mem::take(&mut this.pending).freeze())));
+            }
+            // the tail may still be the start of a delimiter
+            if this.pending.len() >= this.max_record.saturating_add(delim_len) {
+                return Poll::Ready(this.limit_exceeded());
+            }
+            this.searched = (this.pending.len() + 1).saturating_sub(delim_len);
+            let pending = &mut this.pending;
+            let read = poll_pooled_read(Pin::new(&mut this.reader), cx, chunk_size(), |data| {
+                pending.extend_from_slice(data);
+                data.len()
+            });
+            match std::task::ready!(read) {
+                Ok(0) => this.eof = true,
+                Ok(_) => {}
+                Err(err) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
+            }
+        }
+    }
+}
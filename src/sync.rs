@@ -0,0 +1,47 @@
+use std::io::{Read, Write};
+
+use bytes::Bytes;
+
+use crate::{chunk_size, init_buf, pool::PoolRef, take_buf, BufGuard, CopyError, PooledBytes};
+
+/// Read once from a blocking reader into a pooled buffer, the `std::io` counterpart of
+/// [`crate::pooled_read`].
+///
+/// Buffers come from the same global pool the async functions use, so blocking code on a thread
+/// pool and async code in front of it share one set of buffers and size classes. A blocking read
+/// can't give the buffer back while it waits, though, and doesn't wait on
+/// [`crate::BufPoolConfig::max_outstanding_bytes`] either.
+pub fn pooled_read_sync(mut rdr: impl Read) -> Result<Bytes, std::io::Error> {
+    let mut free_buf = BufGuard::new(take_buf(), PoolRef::Global, 0);
+    let buf = init_buf(&mut free_buf, chunk_size());
+    let n = loop {
+        match rdr.read(buf) {
+            Ok(n) => break n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    };
+    Ok(PooledBytes::new(free_buf, n).into())
+}
+
+/// Copy everything from a blocking reader into a blocking writer through one pooled buffer,
+/// returning the byte count, the `std::io` counterpart of [`crate::pooled_copy`].
+///
+/// The writer is flushed at the end. See [`pooled_read_sync`] about the pool.
+pub fn pooled_copy_sync(mut reader: impl Read, mut writer: impl Write) -> Result<u64, CopyError> {
+    let mut free_buf = BufGuard::new(take_buf(), PoolRef::Global, 0);
+    let buf = init_buf(&mut free_buf, chunk_size());
+    let mut total = 0u64;
+    loop {
+        let n = match reader.read(buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(CopyError::Read(err)),
+        };
+        writer.write_all(&buf[..n]).map_err(CopyError::Write)?;
+        total += n as u64;
+    }
+    writer.flush().map_err(CopyError::Flush)?;
+    Ok(total)
+}
@@ -0,0 +1,13 @@
+/// Run a raw syscall wrapper, retrying on `EINTR` and turning negative returns into errors.
+pub(crate) fn retry_eintr(mut f: impl FnMut() -> isize) -> Result<usize, std::io::Error> {
+    loop {
+        let n = f();
+        if n >= 0 {
+            return Ok(n as usize);
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
@@ -0,0 +1,7 @@
+//! `std` has no clock on wasm32-unknown-unknown, where `Instant::now` panics, so in browsers
+//! elapsed time comes from `performance.now()` instead.
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) use std::time::Instant;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) use web_time::Instant;
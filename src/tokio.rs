@@ -0,0 +1,154 @@
+//! The reads and copies for `tokio::io` readers and writers, without going through
+//! `tokio_util::compat`.
+//!
+//! Reads fill pooled buffers through tokio's `ReadBuf`, so the part of a fresh buffer a read
+//! doesn't reach is never zeroed. Buffers come from the same pools as everywhere else in the
+//! crate.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use ::tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use bytes::Bytes;
+use futures_util::future::poll_fn;
+
+use crate::{chunk_size, pool::PoolRef, BufPool, CopyError, HalfClose, PooledBytes, PooledCopy};
+
+/// Read once into a pooled buffer, like [`crate::pooled_read`].
+pub async fn pooled_read(rdr: impl AsyncRead + Unpin) -> Result<Bytes, std::io::Error> {
+    pooled_read_recycled(rdr).await.map(Bytes::from)
+}
+
+/// Like [`crate::pooled_read_recycled`].
+pub async fn pooled_read_recycled(
+    rdr: impl AsyncRead + Unpin,
+) -> Result<PooledBytes, std::io::Error> {
+    read_into(PoolRef::Global, chunk_size(), rdr).await
+}
+
+/// Read once into one of `pool`'s buffers, like [`BufPool::read`].
+pub async fn pooled_read_in(
+    pool: &BufPool,
+    rdr: impl AsyncRead + Unpin,
+) -> Result<Bytes, std::io::Error> {
+    let len = pool.buf_size();
+    read_into(PoolRef::Owned(pool.clone()), len, rdr)
+        .await
+        .map(Bytes::from)
+}
+
+async fn read_into(
+    pool: PoolRef,
+    len: usize,
+    mut rdr: impl AsyncRead + Unpin,
+) -> Result<PooledBytes, std::io::Error> {
+    poll_fn(|cx| {
+        let (mut free_buf, _) = std::task::ready!(pool.poll_acquire(cx, len));
+        let init = free_buf.len();
+        let spare = free_buf.spare_mut();
+        let len = len.min(spare.len());
+        let mut buf = ReadBuf::uninit(&mut spare[..len]);
+        // SAFETY: the buffer tracks how much of it earlier uses initialized
+        unsafe { buf.assume_init(init.min(len)) };
+        let res = crate::retry_interrupted(|| Pin::new(&mut rdr).poll_read(cx, &mut buf));
+        let (filled, init) = (buf.filled().len(), buf.initialized().len());
+        // SAFETY: the reader initialized that much, and ReadBuf keeps it from claiming more
+        unsafe { free_buf.assume_init(init) };
+        res.map_ok(|()| PooledBytes::new(free_buf, filled))
+    })
+    .await
+}
+
+/// Copy everything from `reader` into `writer`, like [`crate::pooled_copy`].
+pub async fn pooled_copy(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<u64, CopyError> {
+    PooledCopy::new(Compat(reader), Compat(writer)).await
+}
+
+/// Copy at most `n` bytes, like [`crate::pooled_copy_n`].
+pub async fn pooled_copy_n(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+    n: u64,
+) -> Result<u64, CopyError> {
+    PooledCopy::new(Compat(reader), Compat(writer))
+        .limit(n)
+        .await
+}
+
+/// Copy through `pool`'s buffers, like [`BufPool::copy`].
+pub async fn pooled_copy_in(
+    pool: &BufPool,
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<u64, CopyError> {
+    PooledCopy::new(Compat(reader), Compat(writer))
+        .pool(pool)
+        .await
+}
+
+/// Pump data both ways between two duplex streams, like [`crate::pooled_copy_bidirectional`].
+pub async fn pooled_copy_bidirectional<A, B>(a: &mut A, b: &mut B) -> Result<(u64, u64), CopyError>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    crate::pooled_copy_bidirectional_with(
+        &mut Compat(a),
+        &mut Compat(b),
+        HalfClose::PropagateShutdown,
+    )
+    .await
+}
+
+/// A tokio reader or writer seen through the `futures` traits, for reusing the copy machinery.
+///
+/// Copies only ever read into initialized pooled buffers, so wrapping them in a `ReadBuf` as-is
+/// is as cheap as it gets.
+struct Compat<T>(T);
+
+impl<T: AsyncRead + Unpin> futures_util::AsyncRead for Compat<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let mut buf = ReadBuf::new(buf);
+        std::task::ready!(Pin::new(&mut self.0).poll_read(cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> futures_util::AsyncWrite for Compat<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.0).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
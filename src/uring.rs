@@ -0,0 +1,251 @@
+use std::collections::{HashMap, VecDeque};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Poll, Waker};
+
+use bytes::Bytes;
+use futures_util::future::poll_fn;
+use io_uring::{opcode, squeue, types, IoUring};
+
+use crate::{chunk_size, init_buf, pool::PoolRef, BufGuard, CopyError, PooledBytes};
+
+/// Submission queue size of the shared ring. Operations past that wait their turn.
+const RING_ENTRIES: u32 = 256;
+
+/// The `user_data` of the driver's read of its own eventfd.
+const WAKEUP: u64 = u64::MAX;
+
+/// Read once from `fd` into a pooled buffer through io_uring, the io_uring counterpart of
+/// [`crate::pooled_read`].
+///
+/// Unlike the poll-based reads, the buffer is handed to the kernel up front, so it is held for as
+/// long as the read waits. Fails with the kernel's error if io_uring isn't available, e.g. before
+/// Linux 5.6 or where it's disabled; [`crate::pooled_read`] is the fallback.
+pub async fn pooled_read_uring(fd: &impl AsRawFd) -> Result<Bytes, std::io::Error> {
+    let fd = types::Fd(fd.as_raw_fd());
+    let len = chunk_size();
+    let (mut buf, _) = poll_fn(|cx| PoolRef::Global.poll_acquire(cx, len)).await;
+    let ptr = init_buf(&mut buf, len).as_mut_ptr();
+    let read = opcode::Read::new(fd, ptr, len as u32)
+        .offset(u64::MAX)
+        .build();
+    let (n, buf) = submit(read, buf).await?;
+    Ok(PooledBytes::new(buf, n).into())
+}
+
+/// Copy everything from `fd_in` to `fd_out`, submitting each read and write of a pooled buffer
+/// through io_uring instead of waiting for readiness. Returns the byte count.
+///
+/// Both descriptors are used from their current positions, and may be files, pipes or sockets.
+/// The buffers come from the global pool, counting against its budget and showing up in its
+/// stats like any others. See [`pooled_read_uring`] about availability.
+pub async fn pooled_copy_uring(
+    fd_in: &impl AsRawFd,
+    fd_out: &impl AsRawFd,
+) -> Result<u64, CopyError> {
+    let (fd_in, fd_out) = (types::Fd(fd_in.as_raw_fd()), types::Fd(fd_out.as_raw_fd()));
+    let len = chunk_size();
+    let mut total = 0u64;
+    loop {
+        let (mut buf, _) = poll_fn(|cx| PoolRef::Global.poll_acquire(cx, len)).await;
+        let ptr = init_buf(&mut buf, len).as_mut_ptr();
+        let read = opcode::Read::new(fd_in, ptr, len as u32)
+            .offset(u64::MAX)
+            .build();
+        let (n, mut buf) = submit(read, buf).await.map_err(CopyError::Read)?;
+        if n == 0 {
+            return Ok(total);
+        }
+        let mut written = 0;
+        while written < n {
+            let rest = &buf[written..n];
+            let write = opcode::Write::new(fd_out, rest.as_ptr(), rest.len() as u32)
+                .offset(u64::MAX)
+                .build();
+            let m;
+            (m, buf) = submit(write, buf).await.map_err(CopyError::Write)?;
+            if m == 0 {
+                return Err(CopyError::Write(std::io::ErrorKind::WriteZero.into()));
+            }
+            written += m;
+        }
+        total += n as u64;
+    }
+}
+
+/// The ring every pooled io_uring operation in the process goes through, driven by a thread of
+/// its own.
+struct Driver {
+    /// Operations for the driver thread to put on the ring.
+    queue: Arc<Mutex<Queue>>,
+    /// An eventfd the driver thread keeps a read on, to notice new operations.
+    wakeup: OwnedFd,
+    /// The error that stopped the driver thread, if it did stop.
+    failed: Arc<AtomicI32>,
+}
+
+/// Operations submitted but not yet on the ring, with their entries.
+type Queue = VecDeque<(squeue::Entry, Arc<Mutex<Op>>)>;
+
+#[derive(Default)]
+struct Op {
+    /// The kernel's result, once it completed.
+    result: Option<i32>,
+    waker: Option<Waker>,
+    /// The buffer the kernel reads into or writes from. It lives here, with the driver holding on
+    /// to it, so it stays put until the kernel is done even if the submitter gave up waiting.
+    buf: Option<BufGuard>,
+}
+
+fn driver() -> Result<&'static Driver, std::io::Error> {
+    static DRIVER: OnceLock<Result<Driver, i32>> = OnceLock::new();
+    DRIVER
+        .get_or_init(|| start().map_err(|err| err.raw_os_error().unwrap_or(libc::ENOSYS)))
+        .as_ref()
+        .map_err(|errno| std::io::Error::from_raw_os_error(*errno))
+}
+
+fn start() -> Result<Driver, std::io::Error> {
+    let ring = IoUring::new(RING_ENTRIES)?;
+    // SAFETY: no pointers involved, and the descriptor is owned right away
+    let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: just created, and nothing else has it
+    let wakeup = unsafe { OwnedFd::from_raw_fd(fd) };
+    let queue = Arc::new(Mutex::new(VecDeque::new()));
+    let failed = Arc::new(AtomicI32::new(0));
+    let (thread_queue, thread_failed) = (queue.clone(), failed.clone());
+    std::thread::Builder::new()
+        .name("bufpool-uring".into())
+        .spawn(move || run(ring, fd, &thread_queue, &thread_failed))?;
+    Ok(Driver {
+        queue,
+        wakeup,
+        failed,
+    })
+}
+
+/// Run one operation on the shared ring, handing back its result along with the buffer it used.
+/// Interrupted operations are resubmitted; on errors the buffer goes back to the pool.
+async fn submit(entry: squeue::Entry, buf: BufGuard) -> Result<(usize, BufGuard), std::io::Error> {
+    let driver = driver()?;
+    let op = Arc::new(Mutex::new(Op {
+        buf: Some(buf),
+        ..Default::default()
+    }));
+    loop {
+        driver
+            .queue
+            .lock()
+            .unwrap()
+            .push_back((entry.clone(), op.clone()));
+        let one = 1u64.to_ne_bytes();
+        // SAFETY: writing 8 bytes from a live buffer, which is all an eventfd takes
+        unsafe { libc::write(driver.wakeup.as_raw_fd(), one.as_ptr().cast(), one.len()) };
+        let res = poll_fn(|cx| {
+            let waker = cx.waker().clone();
+            let mut op = op.lock().unwrap();
+            match op.result.take() {
+                Some(res) => Poll::Ready(res),
+                None => match driver.failed.load(Ordering::Relaxed) {
+                    0 => {
+                        op.waker = Some(waker);
+                        Poll::Pending
+                    }
+                    errno => Poll::Ready(-errno),
+                },
+            }
+        })
+        .await;
+        if res == -libc::EINTR {
+            continue;
+        }
+        if res < 0 {
+            return Err(std::io::Error::from_raw_os_error(-res));
+        }
+        let buf = op.lock().unwrap().buf.take();
+        return Ok((
+            res as usize,
+            buf.expect("only the submitter takes the buffer back"),
+        ));
+    }
+}
+
+/// The driver thread: put queued operations on the ring, wait for completions, and wake whoever
+/// submitted them. Only this thread ever touches the ring.
+fn run(mut ring: IoUring, wakeup: RawFd, queue: &Mutex<Queue>, failed: &AtomicI32) {
+    let mut in_flight: HashMap<u64, Arc<Mutex<Op>>> = HashMap::new();
+    let mut next_id = 0u64;
+    let mut counter = [0u8; 8];
+    let mut armed = false;
+    loop {
+        {
+            let mut sq = ring.submission();
+            if !armed {
+                let read = opcode::Read::new(types::Fd(wakeup), counter.as_mut_ptr(), 8)
+                    .build()
+                    .user_data(WAKEUP);
+                // SAFETY: the counter and the eventfd outlive the ring
+                armed = unsafe { sq.push(&read) }.is_ok();
+            }
+            let mut queue = queue.lock().unwrap();
+            while let Some((entry, op)) = queue.pop_front() {
+                let entry = entry.user_data(next_id);
+                // SAFETY: the buffer the entry points into is kept alive by `op` until completion
+                if unsafe { sq.push(&entry) }.is_err() {
+                    queue.push_front((entry, op));
+                    break;
+                }
+                in_flight.insert(next_id, op);
+                next_id = (next_id + 1) % WAKEUP;
+            }
+        }
+        match ring.submit_and_wait(1) {
+            Ok(_) => {}
+            Err(err) if matches!(err.raw_os_error(), Some(libc::EINTR | libc::EBUSY)) => {}
+            Err(err) => {
+                let errno = err.raw_os_error().unwrap_or(libc::EIO);
+                failed.store(errno, Ordering::Relaxed);
+                // nothing will complete now, so fail everything; the kernel may still be using
+                // the buffers of operations already on the ring, which are leaked instead
+                for op in in_flight.values() {
+                    std::mem::forget(op.lock().unwrap().buf.take());
+                }
+                let queued = std::mem::take(&mut *queue.lock().unwrap());
+                for op in in_flight
+                    .into_values()
+                    .chain(queued.into_iter().map(|(_, op)| op))
+                {
+                    let mut op = op.lock().unwrap();
+                    op.result = Some(-errno);
+                    if let Some(waker) = op.waker.take() {
+                        waker.wake();
+                    }
+                }
+                return;
+            }
+        }
+        let mut done = Vec::new();
+        for cqe in ring.completion() {
+            if cqe.user_data() == WAKEUP {
+                armed = false;
+            } else if let Some(op) = in_flight.remove(&cqe.user_data()) {
+                done.push((op, cqe.result()));
+            }
+        }
+        for (op, res) in done {
+            let mut state = op.lock().unwrap();
+            state.result = Some(res);
+            let waker = state.waker.take();
+            drop(state);
+            // a submitter that gave up leaves its buffer to go back to the pool right here
+            drop(op);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use std::{io::IoSlice, ops::Deref};
+
+use bytes::{Buf, Bytes};
+use futures_util::{future::poll_fn, AsyncWrite, AsyncWriteExt, Sink};
+
+use crate::{chunk_size, init_buf, pool::PoolRef, BufGuard, BufPool, BUF_SIZE, MAX_SEGMENTS};
+
+/// Let `fill` serialize directly into a pooled buffer, writing out whatever it produced each time.
+///
+/// `fill` is called repeatedly with a buffer of the global pool's buffer size, 8 KiB unless
+/// configured otherwise, and returns how many bytes it wrote there; returning zero ends the write.
+/// Resolves to the total number of bytes written.
+pub async fn pooled_write(
+    mut writer: impl AsyncWrite + Unpin,
+    mut fill: impl FnMut(&mut [u8]) -> usize,
+) -> Result<u64, std::io::Error> {
+    let (mut free_buf, _) = poll_fn(|cx| PoolRef::Global.poll_acquire(cx, chunk_size())).await;
+    let mut total = 0u64;
+    loop {
+        let n = fill(init_buf(&mut free_buf, chunk_size())).min(chunk_size());
+        if n == 0 {
+            return Ok(total);
+        }
+        writer.write_all(&free_buf[..n]).await?;
+        total += n as u64;
+    }
+}
+
+/// Write all of `bufs` in order, using `poll_write_vectored` so that e.g. a header and payload can
+/// go out in one syscall.
+///
+/// `bufs` may be `IoSlice`s, `Bytes`, `Vec`s or plain slices. If the writer twice in a row
+/// consumes exactly the first of several non-empty slices, it is assumed to lack vectored support
+/// and the rest is staged through pooled buffers instead, so small slices still coalesce.
+pub async fn pooled_write_vectored(
+    mut writer: impl AsyncWrite + Unpin,
+    bufs: &[impl Deref<Target = [u8]>],
+) -> Result<u64, std::io::Error> {
+    let mut idx = 0;
+    let mut offset = 0;
+    let mut total = 0u64;
+    // the previous write took exactly its first slice, which a vectored writer may also do once
+    let mut suspect = false;
+    while idx < bufs.len() {
+        let first = &bufs[idx][offset..];
+        if first.is_empty() {
+            idx += 1;
+            offset = 0;
+            continue;
+        }
+        let mut rest = bufs[idx..].iter().map(|b| &b[..]).skip(1);
+        let mut offered = 1;
+        let slices: [IoSlice<'_>; MAX_SEGMENTS] = std::array::from_fn(|i| {
+            if i == 0 {
+                return IoSlice::new(first);
+            }
+            match rest.next() {
+                Some(buf) => {
+                    offered += 1;
+                    IoSlice::new(buf)
+                }
+                None => IoSlice::new(&[]),
+            }
+        });
+        let n = writer.write_vectored(&slices[..offered]).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+        let first_only = n == first.len() && slices[1..offered].iter().any(|s| !s.is_empty());
+        total += n as u64;
+        (idx, offset) = advance(bufs, idx, offset, n);
+        if first_only && suspect {
+            total += stage_write(&mut writer, bufs, idx, offset).await?;
+            break;
+        }
+        suspect = first_only;
+    }
+    Ok(total)
+}
+
+/// Move a `(slice, offset)` cursor forward by `n` bytes.
+fn advance(
+    bufs: &[impl Deref<Target = [u8]>],
+    mut idx: usize,
+    mut offset: usize,
+    mut n: usize,
+) -> (usize, usize) {
+    while n > 0 {
+        let left = bufs[idx].len() - offset;
+        if n < left {
+            return (idx, offset + n);
+        }
+        n -= left;
+        idx += 1;
+        offset = 0;
+    }
+    (idx, offset)
+}
+
+/// Copy everything from the cursor onward through pooled buffers, writing each one out in full.
+async fn stage_write(
+    writer: &mut (impl AsyncWrite + Unpin),
+    bufs: &[impl Deref<Target = [u8]>],
+    mut idx: usize,
+    mut offset: usize,
+) -> Result<u64, std::io::Error> {
+    let (mut free_buf, _) = poll_fn(|cx| PoolRef::Global.poll_acquire(cx, chunk_size())).await;
+    let mut total = 0u64;
+    loop {
+        if idx >= bufs.len() {
+            return Ok(total);
+        }
+        let staged = init_buf(&mut free_buf, chunk_size());
+        let mut filled = 0;
+        while filled < staged.len() && idx < bufs.len() {
+            let src = &bufs[idx][offset..];
+            if src.is_empty() {
+                idx += 1;
+                offset = 0;
+                continue;
+            }
+            let n = src.len().min(staged.len() - filled);
+            staged[filled..filled + n].copy_from_slice(&src[..n]);
+            filled += n;
+            (idx, offset) = advance(bufs, idx, offset, n);
+        }
+        writer.write_all(&free_buf[..filled]).await?;
+        total += filled as u64;
+    }
+}
+
+/// Write out everything in `buf`, including non-contiguous ones like `bytes::buf::Chain`.
+///
+/// Segments go out through `poll_write_vectored`, with the same pooled staging fallback as
+/// [`pooled_write_vectored`] for writers that turn out not to support it.
+pub async fn pooled_write_all_buf(
+    mut writer: impl AsyncWrite + Unpin,
+    mut buf: impl Buf,
+) -> Result<u64, std::io::Error> {
+    let mut total = 0u64;
+    let mut suspect = false;
+    while buf.has_remaining() {
+        let (n, first_only) = {
+            let mut slices = [IoSlice::new(&[]); MAX_SEGMENTS];
+            let offered = buf.chunks_vectored(&mut slices);
+            let first = slices[0].len();
+            let n = if offered > 1 {
+                writer.write_vectored(&slices[..offered]).await?
+            } else {
+                writer.write(buf.chunk()).await?
+            };
+            (n, offered > 1 && n == first)
+        };
+        if n == 0 {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+        buf.advance(n);
+        total += n as u64;
+        if first_only && suspect {
+            break;
+        }
+        suspect = first_only;
+    }
+    if !buf.has_remaining() {
+        return Ok(total);
+    }
+    let (mut free_buf, _) = poll_fn(|cx| PoolRef::Global.poll_acquire(cx, chunk_size())).await;
+    while buf.has_remaining() {
+        let staged = init_buf(&mut free_buf, chunk_size());
+        let filled = staged.len().min(buf.remaining());
+        buf.copy_to_slice(&mut staged[..filled]);
+        writer.write_all(&free_buf[..filled]).await?;
+        total += filled as u64;
+    }
+    Ok(total)
+}
+
+/// Pack many small frames into pooled buffers and write them out in as few writes as possible.
+///
+/// Frames larger than a buffer are written directly. The writer itself is not flushed.
+pub async fn pooled_gather_write(
+    writer: impl AsyncWrite + Unpin,
+    frames: impl IntoIterator<Item = impl AsRef<[u8]>>,
+) -> Result<u64, std::io::Error> {
+    let mut buffered = PooledBufWriter::new(writer);
+    let mut total = 0u64;
+    for frame in frames {
+        buffered.write_all(frame.as_ref()).await?;
+        total += frame.as_ref().len() as u64;
+    }
+    futures_util::future::poll_fn(|cx| buffered.poll_flush_buf(cx)).await?;
+    Ok(total)
+}
+
+/// A buffered writer whose buffer is leased from the pool only while it holds unflushed data.
+///
+/// Unlike `futures_util::io::BufWriter`, an idle writer holds no buffer at all, so thousands of
+/// mostly-quiet connections don't each pin their own. Dropping it discards anything not yet
+/// flushed, but the buffer still goes back to the pool.
+pub struct PooledBufWriter<W> {
+    inner: W,
+    /// The leased buffer, whose length is how much has been written into it.
+    buf: Option<BufGuard>,
+    /// How much of the buffer has already been flushed to the inner writer.
+    written: usize,
+    capacity: usize,
+}
+
+impl<W: AsyncWrite + Unpin> PooledBufWriter<W> {
+    /// Wrap a writer with a pooled buffer of the global pool's buffer size, 8 KiB unless
+    /// configured otherwise.
+    pub fn new(inner: W) -> Self {
+        Self::with_capacity(chunk_size(), inner)
+    }
+
+    /// Wrap a writer, coalescing writes into chunks of `capacity` bytes (clamped to 8–64 KiB, and
+    /// to the largest buffer the global pool has).
+    pub fn with_capacity(capacity: usize, inner: W) -> Self {
+        let largest = BufPool::global().classes().last().unwrap_or(BUF_SIZE);
+        Self {
+            inner,
+            buf: None,
+            written: 0,
+            capacity: capacity.clamp(BUF_SIZE, 64 << 10).min(largest),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwrap the writer, discarding any unflushed data.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// The data written but not yet flushed to the inner writer.
+    pub fn buffer(&self) -> &[u8] {
+        match &self.buf {
+            Some(buf) => &buf[self.written..],
+            None => &[],
+        }
+    }
+
+    /// Write out everything buffered, releasing the buffer back to the pool once it is empty.
+    pub(crate) fn poll_flush_buf(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        if let Some(buf) = &self.buf {
+            while self.written < buf.len() {
+                let n = std::task::ready!(
+                    std::pin::Pin::new(&mut self.inner).poll_write(cx, &buf[self.written..])
+                )?;
+                if n == 0 {
+                    return std::task::Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+                }
+                self.written += n;
+            }
+        }
+        self.buf = None;
+        self.written = 0;
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for PooledBufWriter<W> {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        data: &[u8],
+    ) -> std::task::Poll<Result<usize, std::io::Error>> {
+        let this = self.get_mut();
+        // the flushed prefix still takes up room until the whole buffer has gone out
+        let filled = this.buf.as_ref().map_or(0, |buf| buf.len());
+        if filled + data.len() > this.capacity {
+            std::task::ready!(this.poll_flush_buf(cx))?;
+        }
+        if data.len() >= this.capacity {
+            return std::pin::Pin::new(&mut this.inner).poll_write(cx, data);
+        }
+        if this.buf.is_none() {
+            let (mut buf, _) = std::task::ready!(PoolRef::Global.poll_acquire(cx, this.capacity));
+            // the length is used as the fill level here, not the initialized prefix
+            buf.clear();
+            this.buf = Some(buf);
+        }
+        this.buf.as_mut().unwrap().extend_from_slice(data);
+        std::task::Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_flush_buf(cx))?;
+        std::pin::Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_flush_buf(cx))?;
+        std::pin::Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+/// A `Sink<Bytes>` over any writer that batches queued items into pooled buffers.
+///
+/// Items are coalesced through a [`PooledBufWriter`] and only reach the writer once a buffer fills
+/// up or the sink is flushed, which makes this a cheap target for `Stream::forward`.
+pub struct PooledSink<W> {
+    writer: PooledBufWriter<W>,
+    pending: Bytes,
+}
+
+impl<W: AsyncWrite + Unpin> PooledSink<W> {
+    /// Wrap a writer, batching items into pooled buffers of the global pool's buffer size, 8 KiB
+    /// unless configured otherwise.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: PooledBufWriter::new(writer),
+            pending: Bytes::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.writer.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.writer.get_mut()
+    }
+
+    /// Unwrap the writer, discarding anything queued but not yet flushed.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+
+    fn poll_drain_pending(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        while !self.pending.is_empty() {
+            let n = std::task::ready!(
+                std::pin::Pin::new(&mut self.writer).poll_write(cx, &self.pending)
+            )?;
+            if n == 0 {
+                return std::task::Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.pending.advance(n);
+        }
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Sink<Bytes> for PooledSink<W> {
+    type Error = std::io::Error;
+
+    fn poll_ready(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_drain_pending(cx)
+    }
+
+    fn start_send(self: std::pin::Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        debug_assert!(self.pending.is_empty(), "start_send without poll_ready");
+        self.get_mut().pending = item;
+        Ok(())
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_drain_pending(cx))?;
+        std::pin::Pin::new(&mut this.writer).poll_flush(cx)
+    }
+
+    fn poll_close(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_drain_pending(cx))?;
+        std::pin::Pin::new(&mut this.writer).poll_close(cx)
+    }
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use async_io_bufpool::{pooled_broadcast, CopyError, SinkPolicy};
+use common::{pattern, Script};
+use futures_executor::block_on;
+use futures_util::task::noop_waker;
+use futures_util::{AsyncRead, AsyncWrite, FutureExt};
+
+/// Yields `remaining` zero bytes, then EOF.
+struct Zeroes {
+    remaining: usize,
+}
+
+impl AsyncRead for Zeroes {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let n = buf.len().min(self.remaining);
+        buf[..n].fill(0);
+        self.remaining -= n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+/// Fails every write.
+struct Broken;
+
+impl AsyncWrite for Broken {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        _: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(Err(std::io::ErrorKind::ConnectionReset.into()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[test]
+fn broadcast_stops_once_every_sink_failed() {
+    let total = 1 << 20;
+    let mut reader = Zeroes { remaining: total };
+    let mut writers = [Broken, Broken, Broken];
+    let res = block_on(pooled_broadcast(
+        &mut reader,
+        &mut writers,
+        SinkPolicy::Drop,
+    ));
+    match res {
+        Err(CopyError::Write(err)) => assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe),
+        other => panic!("expected the broadcast to fail, got {other:?}"),
+    }
+    // it gave up right away instead of reading the rest with nowhere to send it
+    assert!(reader.remaining > total / 2);
+}
+
+/// Collects writes while `open`, and blocks without ever waking otherwise.
+struct Gated {
+    out: Vec<u8>,
+    open: bool,
+}
+
+impl AsyncWrite for Gated {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if !self.open {
+            return Poll::Pending;
+        }
+        self.out.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[test]
+fn buffer_policy_bounds_the_lag() {
+    let data = pattern(20_000);
+    let mut writers = [
+        Gated {
+            out: Vec::new(),
+            open: true,
+        },
+        Gated {
+            out: Vec::new(),
+            open: false,
+        },
+    ];
+    {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut broadcast = Box::pin(pooled_broadcast(
+            Script::chunks([&data[..]]),
+            &mut writers,
+            SinkPolicy::Buffer(1000),
+        ));
+        assert!(broadcast.poll_unpin(&mut cx).is_pending());
+    }
+    // the fast writer got exactly as far ahead of the stalled one as the policy allows
+    assert_eq!(writers[0].out.len(), 1000);
+    assert!(writers[0].out == data[..1000]);
+}
+
+#[test]
+fn buffer_policy_delivers_everything() {
+    let data = pattern(100_000);
+    let mut writers = [Vec::new(), Vec::new()];
+    let res = block_on(pooled_broadcast(
+        Script::chunks(data.chunks(3000)),
+        &mut writers,
+        SinkPolicy::Buffer(5000),
+    ))
+    .unwrap();
+    assert!(res.iter().all(|r| matches!(r, Ok(100_000))));
+    assert!(writers.iter().all(|w| *w == data));
+}
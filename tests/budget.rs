@@ -0,0 +1,30 @@
+//! Runs against a global pool with a budget of one buffer, so it gets a test binary of its own.
+
+use std::pin::Pin;
+use std::task::Context;
+
+use async_io_bufpool::{init_global, BufPool, BufPoolConfig, MultiWriter, SinkPolicy};
+use futures_executor::block_on;
+use futures_util::task::noop_waker;
+use futures_util::{AsyncWrite, AsyncWriteExt};
+
+#[test]
+fn multi_writer_waits_on_the_budget() {
+    init_global(BufPoolConfig {
+        max_outstanding_bytes: 1,
+        ..Default::default()
+    })
+    .unwrap();
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut writer = MultiWriter::new(vec![Vec::new(), Vec::new()], SinkPolicy::Error);
+
+    let lease = block_on(BufPool::global().acquire());
+    assert!(Pin::new(&mut writer)
+        .poll_write(&mut cx, b"hello")
+        .is_pending());
+    drop(lease);
+    block_on(writer.write_all(b"hello")).unwrap();
+    assert_eq!(writer.writers(), [b"hello".to_vec(), b"hello".to_vec()]);
+    assert_eq!(BufPool::global().stats().outstanding_bytes, 0);
+}
@@ -0,0 +1,210 @@
+//! Mock readers and writers shared by the integration tests.
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::{AsyncRead, AsyncWrite};
+
+/// One step of a [`Script`].
+pub enum Step {
+    /// Hand out these bytes, over as many reads as the caller's buffers need.
+    Data(Vec<u8>),
+    /// Return `Pending` once, waking the task straight away.
+    Pending,
+    /// Fail one read with this error.
+    Fail(std::io::ErrorKind),
+}
+
+/// A reader that plays back scripted short reads, stalls and failures, then reports EOF.
+pub struct Script {
+    steps: VecDeque<Step>,
+    /// How many times the reader has been polled.
+    pub polls: usize,
+}
+
+impl Script {
+    pub fn new(steps: impl IntoIterator<Item = Step>) -> Self {
+        Self {
+            steps: steps.into_iter().collect(),
+            polls: 0,
+        }
+    }
+
+    /// Each of `chunks` as its own read.
+    pub fn chunks<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        Self::new(chunks.into_iter().map(|c| Step::Data(c.to_vec())))
+    }
+
+    /// Like [`Script::chunks`], but stalling once before every read.
+    pub fn stalling<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        Self::new(
+            chunks
+                .into_iter()
+                .flat_map(|c| [Step::Pending, Step::Data(c.to_vec())]),
+        )
+    }
+}
+
+impl AsyncRead for Script {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.polls += 1;
+        match self.steps.pop_front() {
+            None => Poll::Ready(Ok(0)),
+            Some(Step::Pending) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Some(Step::Fail(kind)) => Poll::Ready(Err(kind.into())),
+            Some(Step::Data(mut data)) => {
+                let n = data.len().min(buf.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                if n < data.len() {
+                    self.steps.push_front(Step::Data(data.split_off(n)));
+                }
+                Poll::Ready(Ok(n))
+            }
+        }
+    }
+}
+
+/// A writer that takes at most `max` bytes per write, gathering across vectored slices, and can
+/// be told to fail.
+#[derive(Default)]
+pub struct Partial {
+    pub out: Vec<u8>,
+    pub max: usize,
+    /// Caps for the next few writes, used before falling back to `max`.
+    pub caps: VecDeque<usize>,
+    /// Fail writes once this much has been written.
+    pub fail_after: Option<usize>,
+    pub fail_flush: bool,
+    /// How many slices each vectored write was given.
+    pub vectored: Vec<usize>,
+    pub flushes: usize,
+    pub closed: bool,
+}
+
+impl Partial {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            ..Default::default()
+        }
+    }
+
+    fn room(&self) -> std::io::Result<usize> {
+        match self.fail_after {
+            Some(limit) if self.out.len() >= limit => {
+                Err(std::io::ErrorKind::ConnectionReset.into())
+            }
+            Some(limit) => Ok(self.max.min(limit - self.out.len())),
+            None => Ok(self.max),
+        }
+    }
+}
+
+impl AsyncWrite for Partial {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.poll_write_vectored(cx, &[IoSlice::new(buf)])
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let mut room = self.room()?;
+        if let Some(cap) = self.caps.pop_front() {
+            room = room.min(cap);
+        }
+        self.vectored.push(bufs.len());
+        let mut n = 0;
+        for buf in bufs {
+            let take = buf.len().min(room);
+            self.out.extend_from_slice(&buf[..take]);
+            n += take;
+            room -= take;
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.flushes += 1;
+        if self.fail_flush {
+            return Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.closed = true;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// `len` bytes of a pattern that doesn't repeat every power of two, so misplaced bytes show.
+pub fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// One end of a duplex stream: reads come from `rx`, writes go to `tx`.
+pub struct Duplex {
+    pub rx: Script,
+    pub tx: Partial,
+}
+
+impl Duplex {
+    pub fn new(rx: Script) -> Self {
+        Self {
+            rx,
+            tx: Partial::new(usize::MAX),
+        }
+    }
+}
+
+impl AsyncRead for Duplex {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.rx).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Duplex {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.tx).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.tx).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.tx).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.tx).poll_close(cx)
+    }
+}
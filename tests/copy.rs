@@ -0,0 +1,185 @@
+mod common;
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use async_io_bufpool::{
+    pooled_copy, pooled_copy_bidirectional_with, BufPool, CopyError, HalfClose, PooledCopy,
+    ResumableCopy,
+};
+use common::{pattern, Duplex, Partial, Script};
+use futures_executor::block_on;
+use futures_util::AsyncWrite;
+
+#[test]
+fn stats_count_pool_use_only_for_completed_reads() {
+    let pool = BufPool::new(8192, 16);
+    let data = pattern(400);
+    let mut copy =
+        PooledCopy::new(Script::stalling(data.chunks(100)), Partial::new(usize::MAX)).pool(&pool);
+    assert_eq!(block_on(&mut copy).unwrap(), 400);
+    let stats = *copy.stats();
+    // four chunks and the EOF; the reader stalling before each of them takes nothing from the pool
+    assert_eq!(stats.pool_hits + stats.pool_misses, 5);
+    assert_eq!(stats.chunks, 4);
+    assert_eq!((stats.min_chunk, stats.max_chunk), (100, 100));
+}
+
+#[test]
+fn batch_split_by_partial_vectored_writes() {
+    let data = pattern(4000);
+    let mut writer = Partial::new(1234);
+    let n = block_on(pooled_copy(Script::chunks(data.chunks(1000)), &mut writer)).unwrap();
+    assert_eq!(n, 4000);
+    assert!(writer.out == data);
+    // every write cut a chunk somewhere in the middle, and the rest of the batch followed it
+    assert_eq!(writer.vectored, [4, 3, 2, 1]);
+}
+
+#[test]
+fn batch_of_max_chunks_goes_out_in_one_write() {
+    let data = pattern(8000);
+    let mut writer = Partial::new(usize::MAX);
+    let n = block_on(pooled_copy(Script::chunks(data.chunks(1000)), &mut writer)).unwrap();
+    assert_eq!(n, 8000);
+    assert!(writer.out == data);
+    assert_eq!(writer.vectored, [4, 4]);
+    assert_eq!(writer.flushes, 1);
+}
+
+#[test]
+fn flush_failure_after_the_last_batch() {
+    let data = pattern(3000);
+    let mut writer = Partial::new(usize::MAX);
+    writer.fail_flush = true;
+    let err = block_on(pooled_copy(Script::chunks(data.chunks(1000)), &mut writer)).unwrap_err();
+    assert!(matches!(err, CopyError::Flush(_)), "{err:?}");
+    assert!(writer.out == data);
+}
+
+#[test]
+fn write_failure_mid_batch() {
+    let data = pattern(4000);
+    let mut writer = Partial::new(1000);
+    writer.fail_after = Some(1500);
+    let err = block_on(pooled_copy(Script::chunks(data.chunks(1000)), &mut writer)).unwrap_err();
+    assert!(matches!(err, CopyError::Write(_)), "{err:?}");
+    assert!(writer.out == data[..1500]);
+}
+
+/// Takes at most 700 bytes per write and stalls every other write, noting how far the reader got
+/// ahead of it each time.
+struct Lagging {
+    out: Vec<u8>,
+    read: Arc<AtomicUsize>,
+    max_lag: usize,
+    stall: bool,
+}
+
+impl AsyncWrite for Lagging {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let lag = self.read.load(Ordering::Relaxed) - self.out.len();
+        self.max_lag = self.max_lag.max(lag);
+        self.stall = !self.stall;
+        if self.stall {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let n = buf.len().min(700);
+        self.out.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[test]
+fn max_in_flight_bounds_the_read_ahead() {
+    let data = pattern(20_000);
+    let read = Arc::new(AtomicUsize::new(0));
+    let mut writer = Lagging {
+        out: Vec::new(),
+        read: read.clone(),
+        max_lag: 0,
+        stall: false,
+    };
+    let copy = PooledCopy::new(Script::chunks(data.chunks(1000)), &mut writer)
+        .max_in_flight(1500)
+        .inspect_chunks(move |chunk| {
+            read.fetch_add(chunk.len(), Ordering::Relaxed);
+        });
+    assert_eq!(block_on(copy).unwrap(), 20_000);
+    assert!(writer.out == data);
+    // without the cap the reader would have run a whole batch of chunks ahead
+    assert_eq!(writer.max_lag, 1500);
+}
+
+#[test]
+fn resumable_copy_counters_after_an_interruption() {
+    let data = pattern(10_000);
+    let mut first = Partial::new(700);
+    first.fail_after = Some(2500);
+    let mut copy = ResumableCopy::new(Script::chunks(data.chunks(1000)), &mut first);
+    let interrupted = block_on(&mut copy).unwrap_err();
+    let (reader, _) = copy.into_inner();
+    assert!(matches!(interrupted.error, CopyError::Write(_)));
+    assert_eq!(interrupted.bytes_written, 2500);
+    let read = interrupted.bytes_read as usize;
+    assert!(read > 2500);
+    assert!(interrupted.unwritten == data[2500..read]);
+    assert!(first.out == data[..2500]);
+
+    // the new writer gets exactly what the first one missed, and the totals carry on
+    let mut second = Partial::new(700);
+    let n = block_on(ResumableCopy::resume(reader, &mut second, interrupted)).unwrap();
+    assert_eq!(n, 10_000);
+    assert!(second.out == data[2500..]);
+}
+
+/// `a` has a short message and hits EOF straight away, `b` a longer one trickling in.
+fn half_close(policy: HalfClose) -> (Duplex, Duplex, (u64, u64)) {
+    let long = pattern(5000);
+    let mut a = Duplex::new(Script::chunks([&b"ping"[..]]));
+    let mut b = Duplex::new(Script::stalling(long.chunks(1000)));
+    let counts = block_on(pooled_copy_bidirectional_with(&mut a, &mut b, policy)).unwrap();
+    (a, b, counts)
+}
+
+#[test]
+fn half_close_propagate_shutdown() {
+    let (a, b, counts) = half_close(HalfClose::PropagateShutdown);
+    assert_eq!(counts, (4, 5000));
+    assert_eq!(b.tx.out, b"ping");
+    assert!(a.tx.out == pattern(5000));
+    assert!(a.tx.closed && b.tx.closed);
+}
+
+#[test]
+fn half_close_keep_open() {
+    let (a, b, counts) = half_close(HalfClose::KeepOpen);
+    assert_eq!(counts, (4, 5000));
+    assert!(a.tx.out == pattern(5000));
+    assert!(!a.tx.closed && !b.tx.closed);
+}
+
+#[test]
+fn half_close_close_both() {
+    let (a, b, counts) = half_close(HalfClose::CloseBoth);
+    // b's reader was still stalled when a finished, so nothing of it got through
+    assert_eq!(counts, (4, 0));
+    assert_eq!(b.tx.out, b"ping");
+    assert!(a.tx.out.is_empty());
+    assert!(a.tx.closed && b.tx.closed);
+}
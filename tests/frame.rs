@@ -0,0 +1,128 @@
+mod common;
+
+use async_io_bufpool::{
+    pooled_frames, pooled_read_frame, pooled_write_frame, Endian, FrameConfig, HeaderWidth,
+    LimitExceeded, PooledFrameSink,
+};
+use bytes::Bytes;
+use common::{pattern, Script};
+use futures_executor::block_on;
+use futures_util::{SinkExt, StreamExt, TryStreamExt};
+
+const WIDTHS: [HeaderWidth; 3] = [HeaderWidth::U16, HeaderWidth::U32, HeaderWidth::U64];
+const ENDIANS: [Endian; 2] = [Endian::Big, Endian::Little];
+
+fn config(header_width: HeaderWidth, endian: Endian) -> FrameConfig {
+    FrameConfig {
+        header_width,
+        endian,
+        ..Default::default()
+    }
+}
+
+fn frames() -> Vec<Vec<u8>> {
+    vec![Vec::new(), b"a".to_vec(), pattern(1000), pattern(40_000)]
+}
+
+#[test]
+fn frames_round_trip_in_every_layout() {
+    for width in WIDTHS {
+        for endian in ENDIANS {
+            let config = config(width, endian);
+            let mut wire = Vec::new();
+            for frame in frames() {
+                block_on(pooled_write_frame(&mut wire, &frame, &config)).unwrap();
+            }
+            // three bytes per read, so every header is split across reads
+            let rdr = Script::chunks(wire.chunks(3));
+            let read: Vec<Bytes> = block_on(pooled_frames(rdr, config).try_collect()).unwrap();
+            assert!(read == frames(), "{width:?} {endian:?}");
+        }
+    }
+}
+
+#[test]
+fn header_layouts() {
+    let header = |width, endian| {
+        let mut wire = Vec::new();
+        block_on(pooled_write_frame(
+            &mut wire,
+            &[0; 0x0102],
+            &config(width, endian),
+        ))
+        .unwrap();
+        wire.truncate(wire.len() - 0x0102);
+        wire
+    };
+    assert_eq!(header(HeaderWidth::U16, Endian::Big), [1, 2]);
+    assert_eq!(header(HeaderWidth::U16, Endian::Little), [2, 1]);
+    assert_eq!(header(HeaderWidth::U32, Endian::Big), [0, 0, 1, 2]);
+    assert_eq!(
+        header(HeaderWidth::U64, Endian::Little),
+        [2, 1, 0, 0, 0, 0, 0, 0]
+    );
+}
+
+#[test]
+fn oversized_lengths_are_rejected() {
+    let capped = FrameConfig {
+        max_frame_size: 100,
+        ..Default::default()
+    };
+    // a header claiming more than the cap is refused before anything is allocated for it
+    let wire = [0, 0, 0, 101, 0, 0];
+    let err = block_on(pooled_read_frame(&wire[..], &capped)).unwrap_err();
+    assert!(LimitExceeded::is(&err));
+    let err = block_on(pooled_write_frame(Vec::new(), &[0; 101], &capped)).unwrap_err();
+    assert!(LimitExceeded::is(&err));
+    let huge = [0xff; 8];
+    let err = block_on(pooled_read_frame(
+        &huge[..],
+        &config(HeaderWidth::U64, Endian::Big),
+    ))
+    .unwrap_err();
+    assert!(LimitExceeded::is(&err));
+    // and one the header can't express at all
+    let err = block_on(pooled_write_frame(
+        Vec::new(),
+        &[0; 70_000],
+        &config(HeaderWidth::U16, Endian::Big),
+    ))
+    .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn eof_between_and_inside_frames() {
+    let config = FrameConfig::default();
+    assert!(block_on(pooled_read_frame(&[][..], &config))
+        .unwrap()
+        .is_none());
+    let truncated_header = Script::chunks([&[0, 0][..]]);
+    let err = block_on(pooled_read_frame(truncated_header, &config)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    let truncated_body = Script::chunks([&[0, 0, 0, 5][..], b"abc"]);
+    let err = block_on(pooled_read_frame(truncated_body, &config)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    // the stream yields the error and then ends
+    let mut frames = pooled_frames(Script::chunks([&[0, 0, 0, 1, b'x', 0][..]]), config);
+    assert_eq!(block_on(frames.next()).unwrap().unwrap(), &b"x"[..]);
+    assert!(block_on(frames.next()).unwrap().is_err());
+    assert!(block_on(frames.next()).is_none());
+}
+
+#[test]
+fn frame_sink_round_trip() {
+    let config = config(HeaderWidth::U64, Endian::Little);
+    let mut sink = PooledFrameSink::new(Vec::new(), config);
+    block_on(async {
+        for frame in frames() {
+            sink.feed(Bytes::from(frame)).await.unwrap();
+        }
+        sink.close().await.unwrap();
+    });
+    let wire = sink.into_inner();
+    let read: Vec<Bytes> =
+        block_on(pooled_frames(Script::chunks(wire.chunks(5)), config).try_collect()).unwrap();
+    assert!(read == frames());
+}
@@ -0,0 +1,21 @@
+#![cfg(feature = "metrics")]
+
+use async_io_bufpool::BufPool;
+use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+#[test]
+fn prewarm_leaves_nothing_in_use() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    metrics::with_local_recorder(&recorder, || {
+        assert_eq!(BufPool::new(8192, 16).prewarm(4), 4);
+    });
+
+    let in_use = snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .find(|(key, ..)| key.key().name() == "bufpool_buffers_in_use")
+        .map(|(.., value)| value);
+    assert_eq!(in_use, Some(DebugValue::Gauge(0.0.into())));
+}
@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use async_io_bufpool::{BufPool, BufPoolConfig};
+use futures_executor::block_on;
+use futures_util::FutureExt;
+
+fn budgeted(max_outstanding_bytes: usize) -> BufPool {
+    BufPool::from_config(BufPoolConfig {
+        max_outstanding_bytes,
+        ..Default::default()
+    })
+}
+
+#[test]
+fn acquire_waits_for_the_budget() {
+    let pool = budgeted(8192);
+    let first = block_on(pool.acquire());
+    assert_eq!(pool.stats().outstanding_bytes, 8192);
+    let mut second = Box::pin(pool.acquire());
+    assert!(second.as_mut().now_or_never().is_none());
+    drop(first);
+    let second = second
+        .now_or_never()
+        .expect("the returned buffer should make room");
+    assert_eq!(pool.stats().outstanding_bytes, 8192);
+    drop(second);
+    pool.assert_all_returned();
+}
+
+#[test]
+fn a_budget_below_one_buffer_lets_one_out() {
+    let pool = budgeted(1);
+    let first = pool
+        .acquire()
+        .now_or_never()
+        .expect("one buffer always fits");
+    assert!(pool.acquire().now_or_never().is_none());
+    drop(first);
+    assert!(pool.acquire().now_or_never().is_some());
+}
+
+#[test]
+fn a_waiting_acquire_is_woken_by_a_return() {
+    let pool = budgeted(8192);
+    let first = block_on(pool.acquire());
+    let returner = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        drop(first);
+    });
+    // only resolves if dropping the lease on the other thread wakes this one
+    let second = block_on(pool.acquire());
+    returner.join().unwrap();
+    drop(second);
+    pool.assert_all_returned();
+}
+
+fn shared_only(idle_timeout: Option<Duration>) -> BufPool {
+    BufPool::from_config(BufPoolConfig {
+        idle_timeout,
+        thread_cache: 0,
+        ..Default::default()
+    })
+}
+
+#[test]
+fn idle_timeout_evicts_unused_buffers() {
+    let pool = shared_only(Some(Duration::from_millis(20)));
+    assert_eq!(pool.prewarm(4), 4);
+    assert_eq!(pool.stats().cached_buffers, 4);
+    // sweeps happen as buffers come back, and only free what sat idle for a whole period, so the
+    // first one after startup only marks where that period begins
+    std::thread::sleep(Duration::from_millis(30));
+    drop(block_on(pool.acquire()));
+    assert_eq!(pool.stats().evicted, 0);
+    // the three nobody needed since then go, the one in use stays
+    std::thread::sleep(Duration::from_millis(30));
+    drop(block_on(pool.acquire()));
+    let stats = pool.stats();
+    assert_eq!((stats.evicted, stats.cached_buffers), (3, 1));
+    assert_eq!(stats.cached_bytes, 8192);
+}
+
+#[test]
+fn no_idle_timeout_keeps_buffers() {
+    let pool = shared_only(None);
+    pool.prewarm(4);
+    std::thread::sleep(Duration::from_millis(30));
+    drop(block_on(pool.acquire()));
+    let stats = pool.stats();
+    assert_eq!((stats.evicted, stats.cached_buffers), (0, 4));
+}
+
+#[test]
+fn shrink_frees_idle_buffers_only() {
+    let pool = shared_only(None);
+    pool.prewarm(4);
+    let lease = block_on(pool.acquire());
+    assert_eq!(pool.shrink(), 3 * 8192);
+    let stats = pool.stats();
+    assert_eq!(
+        (stats.evicted, stats.cached_buffers, stats.cached_bytes),
+        (3, 0, 0)
+    );
+    assert_eq!(stats.in_use, 1);
+    assert_eq!(stats.peak_cached_bytes, 4 * 8192);
+    // the lease still comes back afterwards
+    drop(lease);
+    assert_eq!(pool.stats().cached_buffers, 1);
+    pool.assert_all_returned();
+}
+
+#[test]
+fn shrink_empties_the_calling_threads_cache() {
+    let pool = BufPool::from_config(BufPoolConfig::default());
+    let leases: Vec<_> = (0..2).map(|_| block_on(pool.acquire())).collect();
+    drop(leases);
+    assert_eq!(pool.stats().cached_buffers, 2);
+    assert_eq!(pool.shrink(), 2 * 8192);
+    assert_eq!(pool.stats().cached_buffers, 0);
+}
+
+#[test]
+fn stats_count_allocations_reuse_and_discards() {
+    let pool = BufPool::from_config(BufPoolConfig {
+        max_cached_buffers: 2,
+        thread_cache: 0,
+        ..Default::default()
+    });
+    let leases: Vec<_> = (0..3).map(|_| block_on(pool.acquire())).collect();
+    let stats = pool.stats();
+    assert_eq!((stats.allocated, stats.misses, stats.reused), (3, 3, 0));
+    assert_eq!(stats.in_use, 3);
+    drop(leases);
+    let stats = pool.stats();
+    assert_eq!((stats.cached_buffers, stats.discarded), (2, 1));
+    assert_eq!(stats.in_use, 0);
+    let lease = block_on(pool.acquire());
+    let stats = pool.stats();
+    assert_eq!(
+        (stats.allocated, stats.reused, stats.cached_buffers),
+        (3, 1, 1)
+    );
+    drop(lease);
+}
+
+#[test]
+fn prewarm_stops_at_the_caps() {
+    let pool = BufPool::from_config(BufPoolConfig {
+        max_cached_buffers: 3,
+        thread_cache: 0,
+        ..Default::default()
+    });
+    assert_eq!(pool.prewarm(5), 3);
+    let stats = pool.stats();
+    assert_eq!((stats.cached_buffers, stats.cached_bytes), (3, 3 * 8192));
+    assert_eq!(stats.in_use, 0);
+    // the first reads find them ready
+    let lease = block_on(pool.acquire());
+    assert_eq!(pool.stats().reused, 1);
+    drop(lease);
+    pool.assert_all_returned();
+}
@@ -0,0 +1,256 @@
+mod common;
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use async_io_bufpool::{
+    pooled_peek, pooled_read_exact, pooled_read_parse, pooled_read_timeout, pooled_read_to_end,
+    pooled_read_to_string, pooled_read_until, pooled_read_with_deadline, LimitExceeded, Parsed,
+    Rewindable,
+};
+use common::Script;
+use futures_executor::block_on;
+use futures_util::io::BufReader;
+use futures_util::{AsyncRead, AsyncReadExt};
+
+/// Never becomes readable.
+struct Silent;
+
+impl AsyncRead for Silent {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        _: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Pending
+    }
+}
+
+#[test]
+fn read_exact_gathers_short_reads() {
+    let data = common::pattern(20_000);
+    let mut rdr = Script::stalling(data.chunks(3000));
+    let out = block_on(pooled_read_exact(&mut rdr, 15_000)).unwrap();
+    assert!(out == data[..15_000]);
+    // the rest is left for the next read
+    let out = block_on(pooled_read_exact(&mut rdr, 5000)).unwrap();
+    assert!(out == data[15_000..]);
+    assert!(block_on(pooled_read_exact(&mut rdr, 0)).unwrap().is_empty());
+}
+
+#[test]
+fn read_exact_fails_on_early_eof() {
+    let err = block_on(pooled_read_exact(Script::chunks([&b"abc"[..], b"de"]), 6)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    let err = block_on(pooled_read_exact(Script::chunks([]), 1)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn read_to_end_limit_boundary() {
+    let data = common::pattern(10_000);
+    let read = |limit| block_on(pooled_read_to_end(Script::chunks(data.chunks(3000)), limit));
+    assert!(read(10_000).unwrap() == data);
+    assert!(read(10_001).unwrap() == data);
+    let err = read(9_999).unwrap_err();
+    assert!(LimitExceeded::is(&err));
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(
+        read(0).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+}
+
+/// Parses a frame of one length byte followed by that many bytes.
+fn length_prefixed(buf: &[u8]) -> Parsed<Vec<u8>> {
+    match buf.split_first() {
+        Some((&len, rest)) if rest.len() >= len as usize => {
+            Parsed::Done(rest[..len as usize].to_vec(), 1 + len as usize)
+        }
+        _ => Parsed::NeedMore,
+    }
+}
+
+#[test]
+fn read_parse_carries_partial_input_over() {
+    let mut rdr = Script::chunks([&[3, b'a'][..], b"b", b"cXY", b"Z"]);
+    let (frame, rest) = block_on(pooled_read_parse(&mut rdr, 100, length_prefixed)).unwrap();
+    assert_eq!(frame, b"abc");
+    // what the parser didn't consume of the last read comes back, and the rest stays unread
+    assert_eq!(rest, &b"XY"[..]);
+    assert_eq!(block_on(pooled_read_exact(&mut rdr, 1)).unwrap(), &b"Z"[..]);
+}
+
+#[test]
+fn read_parse_done_in_the_first_read() {
+    let rdr = Script::chunks([&[2, b'h', b'i', b'!'][..]]);
+    let (frame, rest) = block_on(pooled_read_parse(rdr, 100, length_prefixed)).unwrap();
+    assert_eq!((&frame[..], &rest[..]), (&b"hi"[..], &b"!"[..]));
+}
+
+#[test]
+fn read_parse_failures() {
+    let parse = |rdr, limit| block_on(pooled_read_parse(rdr, limit, length_prefixed));
+    let err = parse(Script::chunks([&[9, b'a'][..], b"bc"]), 100).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    let err = parse(Script::chunks([&[9, b'a'][..], b"bc", b"def"]), 5).unwrap_err();
+    assert!(LimitExceeded::is(&err));
+    let err = parse(Script::chunks([&[1, b'a'][..]]), 0).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn read_to_string_across_split_code_points() {
+    let text = "caf\u{e9} \u{1f980}";
+    let bytes = text.as_bytes();
+    // cut inside both multi-byte code points
+    let rdr = Script::chunks([&bytes[..4], &bytes[4..8], &bytes[8..]]);
+    assert_eq!(block_on(pooled_read_to_string(rdr, 100)).unwrap(), text);
+    let rdr = Script::chunks([&b"ok \xff"[..]]);
+    assert_eq!(
+        block_on(pooled_read_to_string(rdr, 100))
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::InvalidData
+    );
+    let err = block_on(pooled_read_to_string(Script::chunks([&b"abc"[..]]), 0)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn read_until_delimiter_straddling_reads() {
+    let mut rdr = BufReader::new(Script::chunks([&b"hel"[..], b"lo", b"\nwor", b"ld"]));
+    assert_eq!(
+        block_on(pooled_read_until(&mut rdr, b'\n', 100)).unwrap(),
+        &b"hello\n"[..]
+    );
+    // what followed the delimiter stayed in the reader, and EOF ends the last piece
+    assert_eq!(
+        block_on(pooled_read_until(&mut rdr, b'\n', 100)).unwrap(),
+        &b"world"[..]
+    );
+    assert!(block_on(pooled_read_until(&mut rdr, b'\n', 100))
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn read_until_stops_at_the_limit_across_reads() {
+    let mut rdr = BufReader::new(Script::chunks([&b"abc"[..], b"def\n"]));
+    assert_eq!(
+        block_on(pooled_read_until(&mut rdr, b'\n', 5)).unwrap(),
+        &b"abcde"[..]
+    );
+    assert_eq!(
+        block_on(pooled_read_until(&mut rdr, b'\n', 5)).unwrap(),
+        &b"f\n"[..]
+    );
+    assert_eq!(
+        block_on(pooled_read_until(&mut rdr, b'\n', 0))
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+}
+
+#[test]
+fn read_timeout_fires_on_a_silent_reader() {
+    let start = Instant::now();
+    let err = block_on(pooled_read_timeout(Silent, Duration::from_millis(20))).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    let deadline = Instant::now() + Duration::from_millis(20);
+    let err = block_on(pooled_read_with_deadline(Silent, deadline)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn read_timeout_lets_eof_and_data_through() {
+    // a reader that is ready straight away wins even against an expired timer
+    let eof = block_on(pooled_read_timeout(Script::chunks([]), Duration::ZERO)).unwrap();
+    assert!(eof.is_empty());
+    let read = block_on(pooled_read_timeout(
+        Script::stalling([&b"late"[..]]),
+        Duration::from_secs(10),
+    ))
+    .unwrap();
+    assert_eq!(read, &b"late"[..]);
+    // a timeout too long to add to the clock just never fires
+    let read = block_on(pooled_read_timeout(
+        Script::chunks([&b"x"[..]]),
+        Duration::MAX,
+    ))
+    .unwrap();
+    assert_eq!(read, &b"x"[..]);
+}
+
+fn request() -> Script {
+    Script::chunks([&b"GET /"[..], b"index HTTP/1.1\r\n", b"Host: x\r\n\r\n"])
+}
+
+const REQUEST: &[u8] = b"GET /index HTTP/1.1\r\nHost: x\r\n\r\n";
+
+#[test]
+fn rewind_after_partial_consumption() {
+    let mut rdr = Rewindable::new(request(), 64);
+    let mut method = [0; 3];
+    block_on(rdr.read_exact(&mut method)).unwrap();
+    assert_eq!(&method, b"GET");
+    // the rest of that read was recorded too, though nobody has consumed it yet
+    assert_eq!(rdr.recorded(), b"GET /");
+    rdr.rewind();
+    assert!(!rdr.is_recording());
+    let mut all = Vec::new();
+    block_on(rdr.read_to_end(&mut all)).unwrap();
+    assert_eq!(all, REQUEST);
+    // rewinding again does nothing: recording stopped with the first one
+    rdr.rewind();
+    assert!(rdr.recorded().is_empty());
+}
+
+#[test]
+fn rewind_replays_then_reads_past_the_window() {
+    let mut rdr = Rewindable::new(request(), 8);
+    let mut head = [0; 8];
+    block_on(rdr.read_exact(&mut head)).unwrap();
+    // the recording is full, so reading on before deciding is an error
+    let err = block_on(rdr.read(&mut [0; 1])).unwrap_err();
+    assert!(LimitExceeded::is(&err));
+    rdr.rewind();
+    // one read straddles the end of the replay and the live stream
+    let mut first = [0; 12];
+    block_on(rdr.read_exact(&mut first)).unwrap();
+    assert_eq!(&first, &REQUEST[..12]);
+    assert!(rdr.recorded().is_empty());
+    let mut rest = Vec::new();
+    block_on(rdr.read_to_end(&mut rest)).unwrap();
+    assert_eq!(rest, &REQUEST[12..]);
+}
+
+#[test]
+fn discard_keeps_the_position() {
+    let mut rdr = Rewindable::new(request(), 64);
+    let mut method = [0; 4];
+    block_on(rdr.read_exact(&mut method)).unwrap();
+    rdr.discard();
+    rdr.rewind();
+    let mut rest = Vec::new();
+    block_on(rdr.read_to_end(&mut rest)).unwrap();
+    assert_eq!(rest, &REQUEST[4..]);
+}
+
+#[test]
+fn rewind_a_buffered_reader_after_peeking() {
+    let mut rdr = Rewindable::new(BufReader::new(request()), 64);
+    let sniffed = block_on(pooled_peek(&mut rdr, 3, |head| head == b"GET")).unwrap();
+    assert!(sniffed);
+    let line = block_on(pooled_read_until(&mut rdr, b'\n', 100)).unwrap();
+    assert_eq!(line, &b"GET /index HTTP/1.1\r\n"[..]);
+    rdr.rewind();
+    let line = block_on(pooled_read_until(&mut rdr, b'\n', 100)).unwrap();
+    assert_eq!(line, &b"GET /index HTTP/1.1\r\n"[..]);
+    let line = block_on(pooled_read_until(&mut rdr, b'\n', 100)).unwrap();
+    assert_eq!(line, &b"Host: x\r\n"[..]);
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use async_io_bufpool::{pooled_split, LimitExceeded};
+use bytes::Bytes;
+use common::Script;
+use futures_executor::block_on;
+use futures_util::{StreamExt, TryStreamExt};
+
+fn split(rdr: Script, delimiter: &[u8], max_record: usize) -> Vec<Bytes> {
+    block_on(pooled_split(rdr, delimiter, max_record).try_collect()).unwrap()
+}
+
+#[test]
+fn split_with_the_delimiter_cut_at_every_point() {
+    let wire = b"first\r\n--bsecond\r\n--b\r\n--btail";
+    let expected = [&b"first"[..], b"second", b"", b"tail"];
+    for cut in 1..wire.len() {
+        let rdr = Script::chunks([&wire[..cut], &wire[cut..]]);
+        assert_eq!(split(rdr, b"\r\n--b", 100), expected, "cut at {cut}");
+    }
+    let rdr = Script::chunks(wire.chunks(1));
+    assert_eq!(split(rdr, b"\r\n--b", 100), expected);
+}
+
+#[test]
+fn split_trailing_data() {
+    // a delimiter right at EOF leaves no empty record behind it
+    let rdr = Script::chunks([&b"a--b-"[..], b"-"]);
+    assert_eq!(split(rdr, b"--", 100), [&b"a"[..], b"b"]);
+    // while a partial delimiter at EOF is just part of the last record
+    let rdr = Script::chunks([&b"a--b"[..], b"-"]);
+    assert_eq!(split(rdr, b"--", 100), [&b"a"[..], b"b-"]);
+    assert!(split(Script::chunks([]), b"--", 100).is_empty());
+}
+
+#[test]
+fn split_self_overlapping_delimiter_across_reads() {
+    let rdr = Script::chunks([&b"xaa"[..], b"ab", b"yaab"]);
+    assert_eq!(split(rdr, b"aab", 100), [&b"xa"[..], b"y"]);
+}
+
+#[test]
+fn split_record_limit() {
+    let rdr = Script::chunks([&b"12345|"[..], b"123456|"]);
+    let mut records = pooled_split(rdr, b"|", 5);
+    assert_eq!(block_on(records.next()).unwrap().unwrap(), &b"12345"[..]);
+    let err = block_on(records.next()).unwrap().unwrap_err();
+    assert!(LimitExceeded::is(&err));
+    assert!(block_on(records.next()).is_none());
+    // an over-long record with no delimiter in sight is cut off without reading it all
+    let rdr = Script::chunks(std::iter::repeat_n(&b"xxxx"[..], 1000));
+    let mut records = pooled_split(rdr, b"|", 10);
+    assert!(LimitExceeded::is(
+        &block_on(records.next()).unwrap().unwrap_err()
+    ));
+    assert!(records.into_inner().polls < 10);
+}
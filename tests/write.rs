@@ -0,0 +1,168 @@
+mod common;
+
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use async_io_bufpool::{pooled_write_all_buf, pooled_write_vectored, PooledBufWriter};
+use bytes::{Buf, Bytes};
+use common::{pattern, Partial};
+use futures_executor::block_on;
+use futures_util::task::noop_waker;
+use futures_util::AsyncWrite;
+
+/// Takes up to `budget` bytes in total, then blocks until given more.
+struct Stalling {
+    out: Vec<u8>,
+    budget: usize,
+}
+
+impl AsyncWrite for Stalling {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if self.budget == 0 {
+            return Poll::Pending;
+        }
+        let n = buf.len().min(self.budget);
+        self.budget -= n;
+        self.out.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[test]
+fn write_after_partial_flush() {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let data: Vec<u8> = (0..9000u32).map(|i| i as u8).collect();
+    let mut writer = PooledBufWriter::with_capacity(
+        8192,
+        Stalling {
+            out: Vec::new(),
+            budget: 5000,
+        },
+    );
+
+    let mut w = Pin::new(&mut writer);
+    assert!(matches!(
+        w.as_mut().poll_write(&mut cx, &data[..8000]),
+        Poll::Ready(Ok(8000))
+    ));
+    // 5000 bytes go out, then the writer blocks with 3000 still buffered
+    assert!(w.as_mut().poll_flush(&mut cx).is_pending());
+    assert_eq!(w.buffer().len(), 3000);
+    // the flushed prefix still occupies the buffer, so this has to wait rather than overflow it
+    assert!(w.as_mut().poll_write(&mut cx, &data[8000..]).is_pending());
+
+    w.as_mut().get_mut().get_mut().budget = usize::MAX;
+    assert!(matches!(
+        w.as_mut().poll_write(&mut cx, &data[8000..]),
+        Poll::Ready(Ok(1000))
+    ));
+    assert!(matches!(
+        w.as_mut().poll_flush(&mut cx),
+        Poll::Ready(Ok(()))
+    ));
+    assert_eq!(writer.get_ref().out, data);
+}
+
+/// Takes the first non-empty slice of each write, like any writer without vectored support.
+#[derive(Default)]
+struct Plain {
+    out: Vec<u8>,
+    writes: Vec<usize>,
+}
+
+impl AsyncWrite for Plain {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.out.extend_from_slice(buf);
+        self.writes.push(buf.len());
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[test]
+fn write_vectored_takes_io_slices() {
+    let mut writer = Partial::new(usize::MAX);
+    let bufs = [
+        IoSlice::new(b"head"),
+        IoSlice::new(b""),
+        IoSlice::new(b"body"),
+    ];
+    assert_eq!(
+        block_on(pooled_write_vectored(&mut writer, &bufs)).unwrap(),
+        8
+    );
+    assert_eq!(writer.out, b"headbody");
+    assert_eq!(writer.vectored, [3]);
+}
+
+#[test]
+fn write_vectored_trusts_a_writer_that_took_just_the_first_slice_once() {
+    let mut writer = Partial::new(usize::MAX);
+    writer.caps.push_back(4);
+    let bufs = [
+        Bytes::from_static(b"aaaa"),
+        Bytes::from_static(b"bbbb"),
+        Bytes::from_static(b"cccc"),
+    ];
+    assert_eq!(
+        block_on(pooled_write_vectored(&mut writer, &bufs)).unwrap(),
+        12
+    );
+    assert_eq!(writer.out, b"aaaabbbbcccc");
+    // the rest still went out in one vectored write rather than being staged
+    assert_eq!(writer.vectored, [3, 2]);
+}
+
+#[test]
+fn write_vectored_stages_for_a_writer_without_vectored_support() {
+    let mut writer = Plain::default();
+    let data = pattern(100);
+    let bufs: Vec<&[u8]> = data.chunks(10).collect();
+    assert_eq!(
+        block_on(pooled_write_vectored(&mut writer, &bufs)).unwrap(),
+        100
+    );
+    assert_eq!(writer.out, data);
+    // two single-slice writes give it away, and the remaining eight slices coalesce
+    assert_eq!(writer.writes, [10, 10, 80]);
+}
+
+#[test]
+fn write_all_buf_trusts_a_writer_that_took_just_the_first_chunk_once() {
+    let mut writer = Partial::new(usize::MAX);
+    writer.caps.push_back(4);
+    let buf = Bytes::from_static(b"aaaa")
+        .chain(Bytes::from_static(b"bbbb"))
+        .chain(Bytes::from_static(b"cccc"));
+    assert_eq!(
+        block_on(pooled_write_all_buf(&mut writer, buf)).unwrap(),
+        12
+    );
+    assert_eq!(writer.out, b"aaaabbbbcccc");
+    assert_eq!(writer.vectored, [3, 2]);
+}